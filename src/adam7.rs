@@ -0,0 +1,165 @@
+use crate::ihdr::Ihdr;
+
+/// One of the seven passes of Adam7 interlacing, holding the pixels at
+/// columns `x + k * dx` and rows `y + k * dy` as a reduced image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pass {
+    x: u32,
+    y: u32,
+    dx: u32,
+    dy: u32,
+}
+
+/// The passes in the order their scanlines are stored.
+pub const PASSES: [Pass; 7] = [
+    Pass { x: 0, y: 0, dx: 8, dy: 8 },
+    Pass { x: 4, y: 0, dx: 8, dy: 8 },
+    Pass { x: 0, y: 4, dx: 4, dy: 8 },
+    Pass { x: 2, y: 0, dx: 4, dy: 4 },
+    Pass { x: 0, y: 2, dx: 2, dy: 4 },
+    Pass { x: 1, y: 0, dx: 2, dy: 2 },
+    Pass { x: 0, y: 1, dx: 1, dy: 2 },
+];
+
+impl Pass {
+    /// Width and height of the reduced image of a `width` by `height`
+    /// image. Either is zero when the pass is empty, in which case it
+    /// has no scanlines at all, not even filter type bytes.
+    pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+        (reduced(width, self.x, self.dx), reduced(height, self.y, self.dy))
+    }
+
+    /// Whether the pass holds no pixels of a `width` by `height` image.
+    pub fn is_empty(&self, width: u32, height: u32) -> bool {
+        let (width, height) = self.size(width, height);
+        width == 0 || height == 0
+    }
+
+    /// Copies the pixels of the pass out of the full scanlines in `data`
+    /// into packed scanlines of the reduced image.
+    pub fn gather(&self, header: &Ihdr, data: &[u8]) -> Vec<u8> {
+        let (width, height) = self.size(header.width, header.height);
+        let (row_length, pass_row_length) = (header.row_length(header.width), header.row_length(width));
+        let bits_per_pixel = header.bits_per_pixel();
+
+        let mut pass = vec![0u8; pass_row_length * height as usize];
+        if pass.is_empty() {
+            return pass;
+        }
+
+        for (py, pass_row) in pass.chunks_mut(pass_row_length).enumerate() {
+            let y = self.y as usize + py * self.dy as usize;
+            let row = &data[y * row_length..(y + 1) * row_length];
+            for px in 0..width as usize {
+                copy_pixel(row, self.x as usize + px * self.dx as usize, pass_row, px, bits_per_pixel);
+            }
+        }
+        pass
+    }
+
+    /// Reverses `gather`, copying the pixels of the reduced image in
+    /// `pass` to their place in the full scanlines in `data`.
+    pub fn scatter(&self, header: &Ihdr, pass: &[u8], data: &mut [u8]) {
+        let (width, height) = self.size(header.width, header.height);
+        let (row_length, pass_row_length) = (header.row_length(header.width), header.row_length(width));
+        let bits_per_pixel = header.bits_per_pixel();
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        for (py, pass_row) in pass.chunks(pass_row_length).take(height as usize).enumerate() {
+            let y = self.y as usize + py * self.dy as usize;
+            let row = &mut data[y * row_length..(y + 1) * row_length];
+            for px in 0..width as usize {
+                copy_pixel(pass_row, px, row, self.x as usize + px * self.dx as usize, bits_per_pixel);
+            }
+        }
+    }
+}
+
+
+/// Number of positions from `start` to `size` taken `step` at a time.
+fn reduced(size: u32, start: u32, step: u32) -> u32 {
+    if size > start {
+        (size - start).div_ceil(step)
+    }
+    else {
+        0
+    }
+}
+
+/// Copies pixel `from_x` of the packed scanline `from` to pixel `to_x`
+/// of `to`. Pixels narrower than a byte are packed from the most
+/// significant bit, so only their own bits of the target byte change.
+fn copy_pixel(from: &[u8], from_x: usize, to: &mut [u8], to_x: usize, bits_per_pixel: usize) {
+    if bits_per_pixel >= 8 {
+        let size = bits_per_pixel / 8;
+        to[to_x * size..(to_x + 1) * size].copy_from_slice(&from[from_x * size..(from_x + 1) * size]);
+        return;
+    }
+
+    let mask = (1u8 << bits_per_pixel) - 1;
+    let from_shift = 8 - bits_per_pixel - (from_x * bits_per_pixel) % 8;
+    let to_shift = 8 - bits_per_pixel - (to_x * bits_per_pixel) % 8;
+
+    let value = (from[from_x * bits_per_pixel / 8] >> from_shift) & mask;
+    let byte = &mut to[to_x * bits_per_pixel / 8];
+    *byte = (*byte & !(mask << to_shift)) | (value << to_shift);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::{ColorType, Interlace};
+
+    #[test]
+    fn test_pass_sizes() {
+        let sizes: Vec<(u32, u32)> = PASSES.iter().map(|p| p.size(8, 8)).collect();
+        assert_eq!(sizes, [(1, 1), (1, 1), (2, 1), (2, 2), (4, 2), (4, 4), (8, 4)]);
+
+        let sizes: Vec<(u32, u32)> = PASSES.iter().map(|p| p.size(1, 1)).collect();
+        assert_eq!(sizes, [(1, 1), (0, 1), (1, 0), (0, 1), (1, 0), (0, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn test_every_pixel_in_one_pass() {
+        let (width, height) = (11, 6);
+        let mut seen = vec![0; (width * height) as usize];
+        for pass in PASSES.iter() {
+            let (pass_width, pass_height) = pass.size(width, height);
+            for py in 0..pass_height {
+                for px in 0..pass_width {
+                    seen[((pass.y + py * pass.dy) * width + pass.x + px * pass.dx) as usize] += 1;
+                }
+            }
+        }
+        assert!(seen.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_gather_and_scatter() {
+        for &(bit_depth, color_type) in [(1, ColorType::Grayscale), (4, ColorType::Indexed), (16, ColorType::Rgb)].iter() {
+            let header = Ihdr::new(13, 9, bit_depth, color_type, Interlace::Adam7).unwrap();
+            let row_length = header.row_length(13);
+            let mut data: Vec<u8> = (0..row_length * 9).map(|i| (i * 37 % 251) as u8).collect();
+            let padding = row_length * 8 - 13 * header.bits_per_pixel();
+            for row in data.chunks_mut(row_length) {
+                row[row_length - 1] &= !((1u16 << padding) - 1) as u8;
+            }
+
+            let mut rebuilt = vec![0u8; data.len()];
+            for pass in PASSES.iter() {
+                pass.scatter(&header, &pass.gather(&header, &data), &mut rebuilt);
+            }
+            assert_eq!(rebuilt, data, "{}", header);
+        }
+    }
+
+    #[test]
+    fn test_copy_packed_pixel() {
+        let mut to = [0b1111_1111];
+        copy_pixel(&[0b0100_0000], 0, &mut to, 2, 2);
+        assert_eq!(to, [0b1111_0111]);
+    }
+}
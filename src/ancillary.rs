@@ -0,0 +1,271 @@
+use std::fmt;
+use std::convert::TryFrom;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+
+/// The decoded contents of a standard ancillary chunk. Textual chunks
+/// are decoded by `TextEntry` instead, and `hIST`, `tRNS` and `sPLT` are
+/// left undecoded. A `bKGD` color may be an index into the palette.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ancillary {
+    /// `tIME`: when the image was last modified, in UTC.
+    Time { year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8 },
+    /// `pHYs`: pixels per unit along each axis.
+    PhysicalDimensions { x: u32, y: u32, unit: Unit },
+    /// `gAMA`: image gamma times 100000.
+    Gamma(u32),
+    /// `cHRM`: CIE x and y of the white point and primaries, times 100000.
+    Chromaticities { white: (u32, u32), red: (u32, u32), green: (u32, u32), blue: (u32, u32) },
+    /// `sRGB`: the image uses the sRGB color space.
+    Srgb(RenderingIntent),
+    /// `bKGD`: the preferred background color.
+    Background(Background),
+    /// `sBIT`: the number of significant bits in each channel.
+    SignificantBits(Vec<u8>),
+}
+
+/// The unit of a `pHYs` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// Only the aspect ratio is known.
+    Unknown,
+    Meter,
+}
+
+/// The rendering intent of an `sRGB` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
+}
+
+/// A `bKGD` color, whose layout depends on the color type of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    PaletteIndex(u8),
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+impl Ancillary {
+    /// Chunk types this module can decode.
+    pub const CHUNK_TYPES: [[u8; 4]; 7] = [*b"tIME", *b"pHYs", *b"gAMA", *b"cHRM", *b"sRGB", *b"bKGD", *b"sBIT"];
+
+    /// Whether chunks of `chunk_type` can be decoded into an `Ancillary`.
+    pub fn is_known(chunk_type: &ChunkType) -> bool {
+        Ancillary::CHUNK_TYPES.contains(chunk_type.bytes())
+    }
+}
+
+
+impl TryFrom<&Chunk> for Ancillary {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+        let malformed = |reason: String| PngError::MalformedChunk {
+            chunk_type: chunk.chunk_type().to_string(),
+            reason,
+        };
+        let expect_length = |lengths: &[usize]| {
+            if lengths.contains(&data.len()) {
+                Ok(())
+            }
+            else {
+                Err(malformed(format!("unexpected length {}", data.len())))
+            }
+        };
+
+        match chunk.chunk_type().bytes() {
+            b"tIME" => {
+                expect_length(&[7])?;
+                let (month, day, hour, minute, second) = (data[2], data[3], data[4], data[5], data[6]);
+                if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+                    return Err(malformed(String::from("date or time out of range")));
+                }
+                Ok(Ancillary::Time { year: read_u16(data, 0), month, day, hour, minute, second })
+            }
+            b"pHYs" => {
+                expect_length(&[9])?;
+                let unit = match data[8] {
+                    0 => Unit::Unknown,
+                    1 => Unit::Meter,
+                    other => return Err(malformed(format!("unknown unit {}", other))),
+                };
+                Ok(Ancillary::PhysicalDimensions { x: read_u32(data, 0), y: read_u32(data, 4), unit })
+            }
+            b"gAMA" => {
+                expect_length(&[4])?;
+                Ok(Ancillary::Gamma(read_u32(data, 0)))
+            }
+            b"cHRM" => {
+                expect_length(&[32])?;
+                let point = |i: usize| (read_u32(data, i * 8), read_u32(data, i * 8 + 4));
+                Ok(Ancillary::Chromaticities { white: point(0), red: point(1), green: point(2), blue: point(3) })
+            }
+            b"sRGB" => {
+                expect_length(&[1])?;
+                let intent = match data[0] {
+                    0 => RenderingIntent::Perceptual,
+                    1 => RenderingIntent::RelativeColorimetric,
+                    2 => RenderingIntent::Saturation,
+                    3 => RenderingIntent::AbsoluteColorimetric,
+                    other => return Err(malformed(format!("unknown rendering intent {}", other))),
+                };
+                Ok(Ancillary::Srgb(intent))
+            }
+            b"bKGD" => {
+                // The color type decides the layout, and each layout has its own length.
+                expect_length(&[1, 2, 6])?;
+                let background = match data.len() {
+                    1 => Background::PaletteIndex(data[0]),
+                    2 => Background::Gray(read_u16(data, 0)),
+                    _ => Background::Rgb(read_u16(data, 0), read_u16(data, 2), read_u16(data, 4)),
+                };
+                Ok(Ancillary::Background(background))
+            }
+            b"sBIT" => {
+                expect_length(&[1, 2, 3, 4])?;
+                if data.contains(&0) || data.iter().any(|&bits| bits > 16) {
+                    return Err(malformed(String::from("significant bits must be between 1 and 16")));
+                }
+                Ok(Ancillary::SignificantBits(data.to_vec()))
+            }
+            _ => Err(malformed(String::from("not a known ancillary chunk"))),
+        }
+    }
+}
+
+
+impl fmt::Display for Ancillary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ancillary::Time { year, month, day, hour, minute, second } => {
+                write!(f, "modified {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, hour, minute, second)
+            }
+            Ancillary::PhysicalDimensions { x, y, unit: Unit::Unknown } => write!(f, "aspect ratio {}:{}", x, y),
+            Ancillary::PhysicalDimensions { x, y, unit: Unit::Meter } => {
+                write!(f, "{}x{} pixels per meter", x, y)?;
+                if x == y {
+                    write!(f, " ({:.0} dpi)", *x as f64 * 0.0254)?;
+                }
+                Ok(())
+            }
+            Ancillary::Gamma(gamma) => write!(f, "gamma {}", fixed_point(*gamma)),
+            Ancillary::Chromaticities { white, red, green, blue } => {
+                let point = |(x, y): (u32, u32)| format!("({}, {})", fixed_point(x), fixed_point(y));
+                write!(f, "white {}, red {}, green {}, blue {}", point(*white), point(*red), point(*green), point(*blue))
+            }
+            Ancillary::Srgb(intent) => write!(f, "sRGB, {} rendering intent", intent),
+            Ancillary::Background(Background::PaletteIndex(index)) => write!(f, "background palette entry {}", index),
+            Ancillary::Background(Background::Gray(gray)) => write!(f, "background gray {}", gray),
+            Ancillary::Background(Background::Rgb(r, g, b)) => write!(f, "background RGB ({}, {}, {})", r, g, b),
+            Ancillary::SignificantBits(bits) => {
+                let bits: Vec<String> = bits.iter().map(u8::to_string).collect();
+                write!(f, "significant bits {}", bits.join(", "))
+            }
+        }
+    }
+}
+
+
+impl fmt::Display for RenderingIntent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RenderingIntent::Perceptual => "perceptual",
+            RenderingIntent::RelativeColorimetric => "relative colorimetric",
+            RenderingIntent::Saturation => "saturation",
+            RenderingIntent::AbsoluteColorimetric => "absolute colorimetric",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_be_bytes(bytes)
+}
+
+/// Formats a value stored times 100000, as gAMA and cHRM do.
+fn fixed_point(value: u32) -> String {
+    format!("{:.5}", value as f64 / 100_000.0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn decode(chunk_type: &str, data: &[u8]) -> Result<Ancillary, PngError> {
+        Ancillary::try_from(&Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec()))
+    }
+
+    #[test]
+    fn test_time() {
+        let time = decode("tIME", &[0x07, 0xe8, 5, 1, 12, 34, 56]).unwrap();
+        assert_eq!(time.to_string(), "modified 2024-05-01 12:34:56 UTC");
+        assert!(decode("tIME", &[0x07, 0xe8, 13, 1, 12, 34, 56]).is_err());
+        assert!(decode("tIME", &[0x07, 0xe8, 5, 1, 12, 34]).is_err());
+    }
+
+    #[test]
+    fn test_physical_dimensions() {
+        let phys = decode("pHYs", &[0, 0, 0x0b, 0x13, 0, 0, 0x0b, 0x13, 1]).unwrap();
+        assert_eq!(phys, Ancillary::PhysicalDimensions { x: 2835, y: 2835, unit: Unit::Meter });
+        assert_eq!(phys.to_string(), "2835x2835 pixels per meter (72 dpi)");
+
+        let aspect = decode("pHYs", &[0, 0, 0, 2, 0, 0, 0, 1, 0]).unwrap();
+        assert_eq!(aspect.to_string(), "aspect ratio 2:1");
+        assert!(decode("pHYs", &[0, 0, 0, 2, 0, 0, 0, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_gamma_and_chromaticities() {
+        assert_eq!(decode("gAMA", &45455u32.to_be_bytes()).unwrap().to_string(), "gamma 0.45455");
+
+        let values = [31270u32, 32900, 64000, 33000, 30000, 60000, 15000, 6000];
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+        assert_eq!(
+            decode("cHRM", &data).unwrap().to_string(),
+            "white (0.31270, 0.32900), red (0.64000, 0.33000), green (0.30000, 0.60000), blue (0.15000, 0.06000)"
+        );
+    }
+
+    #[test]
+    fn test_srgb() {
+        assert_eq!(decode("sRGB", &[0]).unwrap(), Ancillary::Srgb(RenderingIntent::Perceptual));
+        assert_eq!(decode("sRGB", &[1]).unwrap().to_string(), "sRGB, relative colorimetric rendering intent");
+        assert!(decode("sRGB", &[4]).is_err());
+    }
+
+    #[test]
+    fn test_background() {
+        assert_eq!(decode("bKGD", &[7]).unwrap(), Ancillary::Background(Background::PaletteIndex(7)));
+        assert_eq!(decode("bKGD", &[1, 0]).unwrap(), Ancillary::Background(Background::Gray(256)));
+        assert_eq!(decode("bKGD", &[0, 255, 0, 128, 0, 0]).unwrap().to_string(), "background RGB (255, 128, 0)");
+        assert!(decode("bKGD", &[0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_significant_bits() {
+        assert_eq!(decode("sBIT", &[5, 6, 5]).unwrap().to_string(), "significant bits 5, 6, 5");
+        assert!(decode("sBIT", &[0]).is_err());
+        assert!(decode("sBIT", &[8, 8, 8, 8, 8]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_chunk() {
+        assert!(!Ancillary::is_known(&ChunkType::from_str("ruSt").unwrap()));
+        assert!(matches!(decode("ruSt", &[]), Err(PngError::MalformedChunk { .. })));
+    }
+}
@@ -0,0 +1,329 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use crate::chunk_type::ChunkType;
+use crate::lsb;
+use crate::validation::Position;
+
+/// Hide secret messages inside PNG files.
+#[derive(Debug, Parser)]
+#[command(name = "png-msg", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    /// Finishes the checks clap cannot express: which positional arguments
+    /// `encode` and `decode` take depends on `--mode`.
+    pub fn resolved(mut self) -> Result<Cli, clap::Error> {
+        let (name, result) = match &mut self.command {
+            Command::Encode(args) => ("encode", args.resolve_positionals()),
+            Command::Decode(args) => ("decode", args.check_chunk_type()),
+            _ => return Ok(self),
+        };
+
+        match result {
+            Ok(()) => Ok(self),
+            Err(message) => {
+                let mut command = Cli::command();
+                command.build();
+                let subcommand = command.find_subcommand_mut(name).expect("the subcommand exists");
+                Err(subcommand.error(ErrorKind::WrongNumberOfValues, message))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Store a message in a new chunk of the given type.
+    #[command(override_usage = "png-msg encode [OPTIONS] <FILE> <CHUNK_TYPE> <MESSAGE> [OUTPUT]\n       png-msg encode --mode lsb [OPTIONS] <FILE> <MESSAGE> [OUTPUT]")]
+    Encode(EncodeArgs),
+    /// Print the message stored in the first chunk of the given type.
+    Decode(DecodeArgs),
+    /// Remove a chunk of the given type.
+    Remove(RemoveArgs),
+    /// List every chunk in the file.
+    Print(PrintArgs),
+    /// Check the chunk order against the PNG specification.
+    Validate(ValidateArgs),
+    /// Generate a key pair for encrypting messages to recipients or for signing.
+    Keygen(KeygenArgs),
+    /// Sign the image data and chosen ancillary chunks with an Ed25519 key.
+    Sign(SignArgs),
+    /// Check the signature stored by `sign`.
+    Verify(VerifyArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct EncodeArgs {
+    /// PNG file to read.
+    pub file: PathBuf,
+
+    /// Four letter chunk type to store the message in (e.g. `ruSt`), left
+    /// out with `--mode lsb`. Then the message to hide and where to write
+    /// the result, which defaults to overwriting `file`.
+    #[arg(value_names = ["CHUNK_TYPE", "MESSAGE", "OUTPUT"], num_args = 1..=3, required = true)]
+    pub positionals: Vec<String>,
+
+    /// The chunk type from `positionals`, set by `Cli::resolved`.
+    #[arg(skip)]
+    pub chunk_type: Option<ChunkType>,
+
+    /// The message from `positionals`.
+    #[arg(skip)]
+    pub message: String,
+
+    /// The output path from `positionals`.
+    #[arg(skip)]
+    pub output: Option<PathBuf>,
+
+    /// Insert the message right before the image data instead of right before IEND.
+    #[arg(long, conflicts_with = "after")]
+    pub before_idat: bool,
+
+    /// Insert the message right after the last chunk of this type.
+    #[arg(long, value_name = "CHUNK_TYPE")]
+    pub after: Option<ChunkType>,
+
+    /// Store the message as a textual entry with this keyword, replacing any
+    /// existing entry with the same keyword. Requires the tEXt, zTXt or iTXt chunk type.
+    #[arg(long)]
+    pub keyword: Option<String>,
+
+    /// Language of the message (e.g. `de-CH`). Requires the iTXt chunk type.
+    #[arg(long, requires = "keyword")]
+    pub language: Option<String>,
+
+    /// The keyword translated into the language of the message.
+    /// Requires the iTXt chunk type.
+    #[arg(long, requires = "keyword")]
+    pub translated_keyword: Option<String>,
+
+    /// Compress the message. With a keyword, requires the iTXt chunk type;
+    /// zTXt is always compressed.
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Encrypt the message with a key derived from this passphrase.
+    /// With `--mode lsb`, it also scatters the message over the pixels.
+    #[arg(long, conflicts_with_all = ["keyword", "recipient"])]
+    pub passphrase: Option<String>,
+
+    /// Encrypt the message to the public key in this file.
+    /// Can be given several times to encrypt to several recipients.
+    #[arg(long, value_name = "PUBLIC_KEY_FILE", conflicts_with = "keyword")]
+    pub recipient: Vec<PathBuf>,
+
+    /// Split the message into numbered chunks of at most this many bytes.
+    /// Decode it with `--segmented`.
+    #[arg(long, value_name = "BYTES", conflicts_with = "keyword")]
+    pub max_chunk_size: Option<usize>,
+
+    /// Where to hide the message.
+    #[arg(long, value_enum, default_value_t)]
+    pub mode: Mode,
+
+    /// With `--mode lsb`, how many low bits of each sample carry the message.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+    pub bits: u8,
+
+    /// With `--mode lsb`, the channels whose samples carry the message,
+    /// separated by commas. Defaults to every channel but alpha.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub channels: Vec<Channel>,
+}
+
+impl EncodeArgs {
+    /// Splits `positionals` into the chunk type, which only `--mode chunk`
+    /// takes, the message and the output path.
+    fn resolve_positionals(&mut self) -> Result<(), String> {
+        let mut values = std::mem::take(&mut self.positionals).into_iter();
+        if self.mode == Mode::Chunk {
+            let chunk_type = values.next().ok_or("a CHUNK_TYPE is required with --mode chunk")?;
+            self.chunk_type = Some(ChunkType::from_str(&chunk_type).map_err(|e| format!("invalid CHUNK_TYPE {:?}: {}", chunk_type, e))?);
+        }
+        self.message = values.next().ok_or("a MESSAGE is required")?;
+        self.output = values.next().map(PathBuf::from);
+
+        if values.next().is_some() {
+            return Err(String::from("too many values, CHUNK_TYPE is not used with --mode lsb"));
+        }
+        Ok(())
+    }
+
+    /// Where the message chunk was asked to go.
+    pub fn position(&self) -> Position {
+        match (&self.after, self.before_idat) {
+            (Some(chunk_type), _) => Position::After(chunk_type.clone()),
+            (None, true) => Position::BeforeIdat,
+            (None, false) => Position::BeforeIend,
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DecodeArgs {
+    /// PNG file to read.
+    pub file: PathBuf,
+
+    /// Chunk type holding the message. Required with `--mode chunk`,
+    /// not used with `--mode lsb`.
+    pub chunk_type: Option<ChunkType>,
+
+    /// Print the textual entry with this keyword. Requires the tEXt, zTXt or iTXt chunk type.
+    #[arg(long)]
+    pub keyword: Option<String>,
+
+    /// Decrypt the message with a key derived from this passphrase.
+    /// With `--mode lsb`, it also finds the pixels holding the message.
+    #[arg(long, conflicts_with_all = ["keyword", "identity"])]
+    pub passphrase: Option<String>,
+
+    /// Decrypt the message with the private key in this file.
+    #[arg(long, value_name = "PRIVATE_KEY_FILE", conflicts_with = "keyword")]
+    pub identity: Option<PathBuf>,
+
+    /// Reassemble a message split into segments before message envelopes
+    /// existed. Enveloped messages record whether they were split.
+    #[arg(long, conflicts_with = "keyword")]
+    pub segmented: bool,
+
+    /// Where to look for the message.
+    #[arg(long, value_enum, default_value_t)]
+    pub mode: Mode,
+
+    /// With `--mode lsb`, how many low bits of each sample carry the message.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+    pub bits: u8,
+
+    /// With `--mode lsb`, the channels whose samples carry the message,
+    /// separated by commas. Defaults to every channel but alpha.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub channels: Vec<Channel>,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+}
+
+impl DecodeArgs {
+    /// Checks that a chunk type is given exactly when `--mode chunk` needs one.
+    fn check_chunk_type(&self) -> Result<(), String> {
+        match (self.mode, &self.chunk_type) {
+            (Mode::Chunk, None) => Err(String::from("a CHUNK_TYPE is required with --mode chunk")),
+            (Mode::Lsb, Some(_)) => Err(String::from("CHUNK_TYPE is not used with --mode lsb")),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RemoveArgs {
+    /// PNG file to modify in place.
+    pub file: PathBuf,
+
+    /// Chunk type to remove.
+    pub chunk_type: ChunkType,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PrintArgs {
+    /// PNG file to read.
+    pub file: PathBuf,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ValidateArgs {
+    /// PNG file to check.
+    pub file: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct KeygenArgs {
+    /// Where to write the private key. The public key is written next to
+    /// it with a `.pub` suffix.
+    pub output: PathBuf,
+
+    /// Generate an Ed25519 key pair for `sign` instead of an X25519 one.
+    #[arg(long)]
+    pub signing: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SignArgs {
+    /// PNG file to sign.
+    pub file: PathBuf,
+
+    /// Signing key file created with `keygen --signing`.
+    #[arg(long)]
+    pub key: PathBuf,
+
+    /// Also sign the chunks of this ancillary type.
+    /// Can be given several times.
+    #[arg(long, value_name = "CHUNK_TYPE")]
+    pub include: Vec<ChunkType>,
+
+    /// Where to write the result. Defaults to overwriting `file`.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct VerifyArgs {
+    /// PNG file to check.
+    pub file: PathBuf,
+
+    /// Public key file of the signer.
+    #[arg(long)]
+    pub key: PathBuf,
+}
+
+/// Where `encode` hides a message and `decode` looks for it.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum Mode {
+    /// In an ancillary chunk.
+    #[default]
+    Chunk,
+    /// In the least significant bits of the pixel samples. The image
+    /// data is decoded and compressed again.
+    Lsb,
+}
+
+/// A channel `--channels` can name, see `lsb::Channel`.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Gray,
+    Alpha,
+}
+
+impl From<Channel> for lsb::Channel {
+    fn from(channel: Channel) -> Self {
+        match channel {
+            Channel::Red => lsb::Channel::Red,
+            Channel::Green => lsb::Channel::Green,
+            Channel::Blue => lsb::Channel::Blue,
+            Channel::Gray => lsb::Channel::Gray,
+            Channel::Alpha => lsb::Channel::Alpha,
+        }
+    }
+}
+
+/// How `print` and `decode` show their results.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum Format {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// JSON, for scripts.
+    Json,
+}
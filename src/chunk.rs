@@ -1,8 +1,15 @@
 use std::fmt;
+use std::io::{self, Write};
+#[cfg(test)]
 use std::string::FromUtf8Error;
 use std::convert::TryFrom;
 use crc::crc32;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use crate::ancillary::Ancillary;
 use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::ihdr::Ihdr;
+use crate::text::TextEntry;
 
 #[derive(Debug)]
 pub struct Chunk {
@@ -13,15 +20,53 @@ pub struct Chunk {
 }
 
 impl Chunk {
+    /// Largest amount of data a chunk may hold, 2^31 - 1 bytes.
+    pub const MAX_LENGTH: usize = (1 << 31) - 1;
+
+    /// Creates a chunk and computes its CRC.
+    ///
+    /// Panics if `data` is longer than `MAX_LENGTH`; use `try_new` for
+    /// data whose size is not known to be small.
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
-        let crc_data: Vec<u8> = chunk_type.bytes.iter().chain(data.iter()).copied().collect();
+        Chunk::try_new(chunk_type, data).expect("chunk data must not exceed 2^31 - 1 bytes")
+    }
 
-        Chunk {
-            length: data.len(),
-            chunk_type: chunk_type,
-            crc: crc32::checksum_ieee(&crc_data[..]),
-            data: data,
+    /// Creates a chunk and computes its CRC, failing if `data` is longer
+    /// than `MAX_LENGTH`.
+    pub fn try_new(chunk_type: ChunkType, data: Vec<u8>) -> Result<Self, PngError> {
+        if data.len() > Chunk::MAX_LENGTH {
+            return Err(PngError::ChunkTooLarge(data.len()));
         }
+
+        let crc = crc32::update(0, &crc32::IEEE_TABLE, &chunk_type.bytes);
+        let crc = crc32::update(crc, &crc32::IEEE_TABLE, &data);
+
+        Ok(
+            Chunk {
+                length: data.len(),
+                chunk_type,
+                crc,
+                data,
+            }
+        )
+    }
+
+    /// Builds a chunk from its parsed parts, checking `crc` against
+    /// the chunk type and data.
+    pub(crate) fn from_parts(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Result<Self, PngError> {
+        let chunk = Chunk::try_new(chunk_type, data)?;
+
+        if chunk.crc != crc {
+            return Err(PngError::CrcMismatch { expected: chunk.crc, actual: crc });
+        }
+
+        Ok(chunk)
+    }
+
+    /// Builds a chunk from its parsed parts, keeping `crc` even if it does
+    /// not match. Meant for inspecting corrupted files.
+    pub(crate) fn from_parts_unchecked(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Self {
+        Chunk { crc, ..Chunk::new(chunk_type, data) }
     }
 
     pub fn length(&self) -> u32 {
@@ -40,38 +85,56 @@ impl Chunk {
         self.crc
     }
 
+    /// Returns `true` if the stored CRC matches the chunk type and data.
+    pub fn has_valid_crc(&self) -> bool {
+        let crc = crc32::update(0, &crc32::IEEE_TABLE, &self.chunk_type.bytes);
+        crc32::update(crc, &crc32::IEEE_TABLE, &self.data) == self.crc
+    }
+
+    #[cfg(test)]
     pub fn data_as_string(&self) -> Result<String, FromUtf8Error> {
         String::from_utf8(self.data.clone())
     }
 
+    #[cfg(test)]
     pub fn as_bytes(&self) -> Vec<u8> {
-        let length_bytes = (self.length as u32).to_be_bytes();
-        let crc_bytes = self.crc.to_be_bytes();
+        let mut bytes = Vec::with_capacity(self.length + 12);
+        self.write_to(&mut bytes).expect("writing to a Vec cannot fail");
+        bytes
+    }
 
-        length_bytes.iter()
-            .chain(self.chunk_type.bytes.iter())
-            .chain(self.data.iter())
-            .chain(crc_bytes.iter())
-            .copied()
-            .collect()
+    /// Serializes the chunk straight into `writer`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&(self.length as u32).to_be_bytes())?;
+        writer.write_all(&self.chunk_type.bytes)?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.crc.to_be_bytes())
     }
 }
 
 
 impl TryFrom<&[u8]> for Chunk {
-    type Error = &'static str;
+    type Error = PngError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         if value.len() < 12 {
-            return Err("Invalid length");
+            return Err(PngError::TruncatedChunk { needed: 12, available: value.len() });
         }
 
         let mut length = [0u8; 4];
         length.copy_from_slice(&value[0..4]);
         let length = u32::from_be_bytes(length) as usize;
 
-        if value.len() - length as usize != 12 {
-            return Err("Invalid input");
+        if length > Chunk::MAX_LENGTH {
+            return Err(PngError::ChunkTooLarge(length));
+        }
+
+        if value.len() < length + 12 {
+            return Err(PngError::TruncatedChunk { needed: length + 12, available: value.len() });
+        }
+
+        if value.len() > length + 12 {
+            return Err(PngError::LengthMismatch { declared: length, actual: value.len() - 12 });
         }
 
         let mut chunk_type_bytes = [0u8; 4];
@@ -79,36 +142,68 @@ impl TryFrom<&[u8]> for Chunk {
 
         let chunk_type = ChunkType::try_from(chunk_type_bytes)?;
 
-        let data_len = value.len() - 12;
-        let data_and_crc = &value[8..];
-        
-        let mut data = Vec::with_capacity(data_len);
-        data.extend_from_slice(&data_and_crc[..data_len]);
+        let data = value[8..value.len() - 4].to_vec();
 
         let mut crc = [0u8; 4];
-        crc.copy_from_slice(&value[8 + data_len..]);
+        crc.copy_from_slice(&value[value.len() - 4..]);
         let crc = u32::from_be_bytes(crc);
 
-        let checksum = crc32::checksum_ieee(&value[4..8+data_len]);
-        if crc != checksum {
-            return Err("Invalid CRC");
-        }
-
-        Ok(
-            Chunk {
-                length,
-                chunk_type,
-                data: data.to_vec(),
-                crc,
-            }
-        )
+        Chunk::from_parts(chunk_type, data, crc)
     }
 }
 
 
+/// Shows the type, length, CRC and property flags of the chunk, followed
+/// by the decoded contents on a second line for chunk types this crate knows.
 impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "")
+        let chunk_type = &self.chunk_type;
+        write!(f, "{}\t{:>10} bytes\tcrc {:08x}", chunk_type, self.length(), self.crc())?;
+        write!(f, "{}\t", if self.has_valid_crc() { "" } else { " (mismatch)" })?;
+        write!(f, "{}, ", if chunk_type.is_critical() { "critical" } else { "ancillary" })?;
+        write!(f, "{}, ", if chunk_type.is_public() { "public" } else { "private" })?;
+        write!(f, "{}", if chunk_type.is_safe_to_copy() { "safe to copy" } else { "unsafe to copy" })?;
+
+        let details = if chunk_type.bytes() == b"IHDR" {
+            Some(Ihdr::try_from(self).map(|ihdr| ihdr.to_string()))
+        }
+        else if TextEntry::is_textual(chunk_type) {
+            Some(TextEntry::try_from(self).map(|entry| entry.to_string()))
+        }
+        else if Ancillary::is_known(chunk_type) {
+            Some(Ancillary::try_from(self).map(|ancillary| ancillary.to_string()))
+        }
+        else {
+            None
+        };
+
+        match details {
+            Some(Ok(details)) => write!(f, "\n\t{}", details),
+            Some(Err(e)) => write!(f, "\n\t{}", e),
+            None => Ok(()),
+        }
+    }
+}
+
+
+/// Serializes the type, length and CRC of the chunk along with whether
+/// the CRC is valid and, for textual chunks, the decoded entry.
+impl Serialize for Chunk {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let text = if TextEntry::is_textual(&self.chunk_type) {
+            TextEntry::try_from(self).ok()
+        }
+        else {
+            None
+        };
+
+        let mut state = serializer.serialize_struct("Chunk", 5)?;
+        state.serialize_field("type", &self.chunk_type)?;
+        state.serialize_field("length", &self.length())?;
+        state.serialize_field("crc", &self.crc)?;
+        state.serialize_field("crc_valid", &self.has_valid_crc())?;
+        state.serialize_field("text", &text)?;
+        state.end()
     }
 }
 
@@ -198,6 +293,73 @@ mod tests {
 
         let chunk = Chunk::try_from(chunk_data.as_ref());
 
-        assert!(chunk.is_err());
+        assert!(matches!(
+            chunk,
+            Err(PngError::CrcMismatch { expected: 2882656334, actual: 2882656333 })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_chunk_type_from_bytes() {
+        let chunk_data = Chunk::new(ChunkType::from_bytes_lenient([82, 117, 49, 116]), vec![1, 2, 3]).as_bytes();
+        let chunk = Chunk::try_from(chunk_data.as_ref());
+
+        assert!(matches!(chunk, Err(PngError::InvalidChunkType([82, 117, 49, 116]))));
+    }
+
+    #[test]
+    fn test_truncated_chunk_from_bytes() {
+        let chunk_data = testing_chunk().as_bytes();
+        let chunk = Chunk::try_from(&chunk_data[..chunk_data.len() - 1]);
+
+        assert!(matches!(chunk, Err(PngError::TruncatedChunk { needed: 54, available: 53 })));
+    }
+
+    #[test]
+    fn test_chunk_display() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"hello".to_vec());
+        assert_eq!(chunk.to_string(), format!("RuSt\t         5 bytes\tcrc {:08x}\tcritical, private, safe to copy", chunk.crc()));
+
+        let chunk = Chunk::new(ChunkType::from_str("gAMA").unwrap(), 45455u32.to_be_bytes().to_vec());
+        assert!(chunk.to_string().ends_with("ancillary, public, unsafe to copy\n\tgamma 0.45455"));
+
+        let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Title\0Dice".to_vec());
+        assert!(chunk.to_string().ends_with("safe to copy\n\tTitle: Dice"));
+
+        let chunk = Chunk::new(ChunkType::from_str("sRGB").unwrap(), vec![9]);
+        assert!(chunk.to_string().ends_with("\n\tmalformed sRGB chunk: unknown rendering intent 9"));
+    }
+
+    #[test]
+    fn test_unchecked_crc() {
+        let chunk = Chunk::from_parts_unchecked(ChunkType::from_str("RuSt").unwrap(), b"hello".to_vec(), 1);
+
+        assert!(!chunk.has_valid_crc());
+        assert_eq!(chunk.crc(), 1);
+        assert!(chunk.to_string().contains("crc 00000001 (mismatch)"));
+        assert!(testing_chunk().has_valid_crc());
+    }
+
+    #[test]
+    fn test_chunk_serialize() {
+        let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Title\0Dice".to_vec());
+        let json = serde_json::to_value(&chunk).unwrap();
+
+        assert_eq!(json["type"]["name"], "tEXt");
+        assert_eq!(json["type"]["critical"], false);
+        assert_eq!(json["length"], 10);
+        assert_eq!(json["crc"], chunk.crc());
+        assert_eq!(json["crc_valid"], true);
+        assert_eq!(json["text"], serde_json::json!({ "keyword": "Title", "text": "Dice" }));
+
+        let json = serde_json::to_value(testing_chunk()).unwrap();
+        assert!(json["text"].is_null());
+    }
+
+    #[test]
+    fn test_declared_length_too_large() {
+        let mut bytes = testing_chunk().as_bytes();
+        bytes[0] = 0x80;
+        assert!(matches!(Chunk::try_from(&bytes[..]), Err(PngError::ChunkTooLarge(_))));
     }
 }
@@ -1,14 +1,23 @@
 use std::fmt;
 use std::convert::TryFrom;
-use std::str::{self, FromStr};
+use std::str::FromStr;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use crate::error::PngError;
 
 /// A 4-byte chunk type code.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ChunkType {
     pub bytes: [u8; 4],
 }
 
 impl ChunkType {
+    /// Creates a chunk type from any four bytes, without checking that
+    /// they are ASCII letters. Meant for tools that need to inspect
+    /// malformed files; use `TryFrom` everywhere else.
+    pub fn from_bytes_lenient(bytes: [u8; 4]) -> Self {
+        ChunkType { bytes }
+    }
+
     pub fn bytes(&self) -> &[u8; 4] {
         &self.bytes
     }
@@ -57,48 +66,62 @@ impl ChunkType {
 
 
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = &'static str;
+    type Error = PngError;
 
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
+        if !value.iter().all(u8::is_ascii_alphabetic) {
+            return Err(PngError::InvalidChunkType(value));
+        }
+
         Ok(ChunkType { bytes: value })
     }
 }
 
 
 impl FromStr for ChunkType {
-    type Err = &'static str;
+    type Err = PngError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.bytes().count() != 4 {
-            return Err("Invalid string length");
-        }
-
-        if !s.is_ascii() {
-            return Err("Invalid string")
-        }
-
-        for (_, c) in s.bytes().enumerate() {
-            if !c.is_ascii_alphabetic() {
-                return Err("Invalid string");
-            }
+        if s.len() != 4 {
+            return Err(PngError::InvalidChunkTypeLength(s.len()));
         }
 
         let mut bytes: [u8; 4] = [0; 4];
         bytes.clone_from_slice(&s.as_bytes()[0..4]);
 
-        Ok( ChunkType { bytes })
+        ChunkType::try_from(bytes)
     }
 }
 
 
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Ok(s) = str::from_utf8(&self.bytes) {
-            write!(f, "{}", s)
-        }
-        else {
-            Err(fmt::Error)
+        // Lenient chunk types may hold any bytes, so escape the ones
+        // that can't be printed as-is.
+        for byte in self.bytes.iter() {
+            if byte.is_ascii_graphic() {
+                write!(f, "{}", *byte as char)?;
+            }
+            else {
+                write!(f, "\\x{:02x}", byte)?;
+            }
         }
+
+        Ok(())
+    }
+}
+
+
+/// Serializes the type as text along with its property bits.
+impl Serialize for ChunkType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ChunkType", 5)?;
+        state.serialize_field("name", &self.to_string())?;
+        state.serialize_field("critical", &self.is_critical())?;
+        state.serialize_field("public", &self.is_public())?;
+        state.serialize_field("reserved_bit_valid", &self.is_reserved_bit_valid())?;
+        state.serialize_field("safe_to_copy", &self.is_safe_to_copy())?;
+        state.end()
     }
 }
 
@@ -117,6 +140,19 @@ mod tests {
         assert_eq!(expected, actual.bytes());
     }
 
+    #[test]
+    pub fn test_chunk_type_from_invalid_bytes() {
+        let actual = ChunkType::try_from([82, 117, 49, 116]);
+        assert!(matches!(actual, Err(PngError::InvalidChunkType([82, 117, 49, 116]))));
+    }
+
+    #[test]
+    pub fn test_chunk_type_from_bytes_lenient() {
+        let chunk = ChunkType::from_bytes_lenient([82, 0, 49, 255]);
+        assert_eq!(chunk.bytes(), &[82, 0, 49, 255]);
+        assert_eq!(&chunk.to_string(), "R\\x001\\xff");
+    }
+
     #[test]
     pub fn test_chunk_type_from_str() {
         let expected = ChunkType::try_from([82, 117, 83, 116]).unwrap();
@@ -184,7 +220,10 @@ mod tests {
         assert!(!chunk.is_valid());
 
         let chunk = ChunkType::from_str("Ru1t");
-        assert!(chunk.is_err());
+        assert!(matches!(chunk, Err(PngError::InvalidChunkType(_))));
+
+        let chunk = ChunkType::from_str("RuStacean");
+        assert!(matches!(chunk, Err(PngError::InvalidChunkTypeLength(9))));
     }
 
     #[test]
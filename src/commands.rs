@@ -0,0 +1,1068 @@
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use x25519_dalek::StaticSecret;
+use fehler::{throw, throws};
+use crate::Error;
+use crate::args::{self, DecodeArgs, EncodeArgs, Format, KeygenArgs, Mode, PrintArgs, RemoveArgs, SignArgs, ValidateArgs, VerifyArgs};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crypto;
+use crate::editor::PngEditor;
+use crate::error::PngError;
+use crate::filter::FilterStrategy;
+use crate::image::Image;
+use crate::keys;
+use crate::lsb::{self, Embedding};
+use crate::message::{self, ContentType, Decryption, Encryption, Envelope};
+use crate::png::Png;
+use crate::reader::PngReader;
+use crate::segment;
+use crate::signature::SignatureChunk;
+use crate::text::{InternationalTextChunk, TextEntry};
+use crate::validation::Position;
+
+/// Adds a new chunk containing `args.message` to the PNG file, by default
+/// right before the `IEND` chunk. The other chunks are copied over from
+/// the original file untouched.
+///
+/// With a keyword, the message is stored as a tEXt, zTXt or iTXt entry
+/// that replaces any textual entry with the same keyword; those chunk
+/// types require one. Otherwise it is wrapped in a message envelope,
+/// optionally compressed, encrypted and split into numbered segments.
+/// In lsb mode the envelope is hidden in the pixels instead, see `encode_lsb`.
+#[throws]
+pub fn encode(args: EncodeArgs) {
+    if args.mode == Mode::Lsb {
+        return encode_lsb(args)?;
+    }
+    let chunk_type = required_chunk_type(&args.chunk_type)?.clone();
+    if !chunk_type.is_valid() {
+        throw!(format!("{} is not a valid chunk type", chunk_type));
+    }
+    if TextEntry::is_textual(&chunk_type) && args.keyword.is_none() {
+        throw!(format!("{} chunks hold textual entries, --keyword is required", chunk_type));
+    }
+
+    let position = args.position();
+    let mut editor = PngEditor::open(BufReader::new(File::open(&args.file)?))?;
+
+    let chunks = match &args.keyword {
+        Some(keyword) => {
+            check_text_chunk_type(&chunk_type)?;
+            let entry = text_entry(&args, &chunk_type, keyword)?;
+            for chunk_type in TextEntry::CHUNK_TYPES.iter() {
+                let chunk_type = ChunkType { bytes: *chunk_type }.to_string();
+                editor.remove_chunks_where(&chunk_type, |c| Ok(TextEntry::try_from(c).is_ok_and(|e| e.keyword() == keyword)))?;
+            }
+            vec![entry.to_chunk()]
+        }
+        None => {
+            let encryption = encryption(&args.passphrase, &args.recipient)?;
+            message::pack(args.message.as_bytes(), ContentType::Text, args.compress, &encryption, args.max_chunk_size)?
+                .into_iter()
+                .map(|data| Chunk::try_new(chunk_type.clone(), data))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    editor.insert_chunks(chunks, &position)?;
+
+    let output = args.output.unwrap_or(args.file);
+    write_atomically(&output, |writer| editor.write_to(writer))?;
+}
+
+/// Hides the message envelope in the low bits of the pixel samples and
+/// compresses the image data again. Other chunks are kept, except those
+/// unsafe to copy once the image changes, such as signatures.
+/// The samples are visited in an order derived from the image dimensions,
+/// or from the passphrase if there is one; only the latter hides where the
+/// message is from someone who knows this tool.
+#[throws]
+fn encode_lsb(args: EncodeArgs) {
+    if args.keyword.is_some() || args.max_chunk_size.is_some() || args.before_idat || args.after.is_some() {
+        throw!("--keyword, --max-chunk-size, --before-idat and --after only apply to --mode chunk");
+    }
+
+    let reader = PngReader::new(BufReader::new(File::open(&args.file)?))?;
+    let mut png = Png::from_chunks(reader.collect::<Result<_, _>>()?);
+    let mut image = png.image()?;
+    let mut embedding = Embedding::new(image.header(), args.bits, &lsb_channels(&args.channels))?;
+    if let Some(passphrase) = &args.passphrase {
+        embedding = embedding.scattered(passphrase)?;
+    }
+
+    let encryption = encryption(&args.passphrase, &args.recipient)?;
+    let envelope = message::pack(args.message.as_bytes(), ContentType::Text, args.compress, &encryption, None)?;
+    embedding.embed(&mut image, &envelope[0])?;
+    png.replace_image_data(image.to_idat_chunks(FilterStrategy::Adaptive, Image::DEFAULT_IDAT_SIZE)?)?;
+
+    let output = args.output.unwrap_or(args.file);
+    write_atomically(&output, |writer| png.write_to(writer))?;
+}
+
+/// The channels named on the command line, as `Embedding` takes them.
+fn lsb_channels(channels: &[args::Channel]) -> Vec<lsb::Channel> {
+    channels.iter().map(|&channel| channel.into()).collect()
+}
+
+/// How the message should be encrypted, from the options of `encode`.
+#[throws]
+fn encryption(passphrase: &Option<String>, recipients: &[PathBuf]) -> Encryption {
+    if let Some(passphrase) = passphrase {
+        Encryption::Passphrase(passphrase.clone())
+    }
+    else if !recipients.is_empty() {
+        let recipients = recipients.iter()
+            .map(|path| keys::load_public_key(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        Encryption::Recipients(recipients)
+    }
+    else {
+        Encryption::None
+    }
+}
+
+/// Prints the message stored in the first chunk of `args.chunk_type`,
+/// or in the textual entry of that type with `args.keyword`, decrypting
+/// it if a passphrase or identity is given. The file is streamed, so
+/// chunks before the message are never buffered.
+///
+/// Textual entries are never encrypted or segmented. Segmented messages
+/// are reassembled from every chunk of the type, and chunks written
+/// before message envelopes existed are decoded as raw data.
+/// In lsb mode the message is read from the pixels instead, see `decode_lsb`.
+#[throws]
+pub fn decode(args: DecodeArgs) {
+    if args.mode == Mode::Lsb {
+        return decode_lsb(args)?;
+    }
+
+    let decoded = decode_chunks(&args)?;
+    match args.format {
+        Format::Text => println!("{}", decoded.message),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&decoded)?),
+    }
+}
+
+/// A message read from chunks by `decode`, as printed with `--format json`.
+/// The chunk is the first one holding the message, with its byte offset
+/// in the file as `print` reports it.
+#[derive(serde::Serialize)]
+struct DecodedMessage {
+    offset: usize,
+    #[serde(flatten)]
+    chunk: Chunk,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    segments: Option<usize>,
+    message: String,
+}
+
+/// Finds and decodes the message `decode` prints.
+#[throws]
+fn decode_chunks(args: &DecodeArgs) -> DecodedMessage {
+    let requested_type = required_chunk_type(&args.chunk_type)?;
+    let chunk_type = requested_type.to_string();
+    if TextEntry::is_textual(requested_type) && (args.passphrase.is_some() || args.identity.is_some() || args.segmented) {
+        throw!(format!("{} entries are never encrypted or segmented, --passphrase, --identity and --segmented do not apply", chunk_type));
+    }
+
+    let mut reader = PngReader::new(BufReader::new(File::open(&args.file)?))?;
+
+    let mut chunks = vec![];
+    if let Some(keyword) = &args.keyword {
+        check_text_chunk_type(requested_type)?;
+        loop {
+            match reader.find_chunk(&chunk_type)? {
+                Some(chunk) if TextEntry::try_from(&chunk).is_ok_and(|e| e.keyword() == keyword) => break chunks.push(chunk),
+                Some(_) => continue,
+                None => throw!(format!("no {} entry with keyword {:?} found", chunk_type, keyword)),
+            }
+        }
+    }
+    else {
+        chunks.extend(reader.find_chunk(&chunk_type)?);
+    }
+
+    let offset = reader.chunk_offset();
+    let envelope = match chunks.first() {
+        Some(chunk) if !TextEntry::is_textual(chunk.chunk_type()) && Envelope::is_envelope(chunk.data()) => Some(Envelope::try_from(chunk.data())?),
+        Some(_) => None,
+        None => throw!(PngError::ChunkNotFound(chunk_type)),
+    };
+    let segmented = match &envelope {
+        Some(envelope) => envelope.flags() & Envelope::SEGMENTED != 0,
+        None => args.segmented,
+    };
+    if segmented && args.keyword.is_none() {
+        while let Some(chunk) = reader.find_chunk(&chunk_type)? {
+            chunks.push(chunk);
+        }
+    }
+
+    let identity = args.identity.as_deref().map(keys::load_private_key).transpose()?;
+    let decryption = decryption(&args.passphrase, &identity);
+
+    let chunk = &chunks[0];
+    let message = if TextEntry::is_textual(chunk.chunk_type()) {
+        TextEntry::try_from(chunk)?.text().to_string()
+    }
+    else if envelope.is_some() {
+        match message::unpack(chunks.iter().map(Chunk::data), &decryption)? {
+            (ContentType::Text, body) => String::from_utf8(body)?,
+            (ContentType::Binary, body) => hex::encode(body),
+        }
+    }
+    else {
+        let data = if segmented {
+            segment::join(chunks.iter().map(Chunk::data))?
+        }
+        else {
+            chunk.data().to_vec()
+        };
+
+        let data = match decryption {
+            Decryption::Passphrase(passphrase) => crypto::decrypt(&data, passphrase)?,
+            Decryption::Identity(identity) => crypto::decrypt_with_identity(&data, identity)?,
+            Decryption::None => data,
+        };
+        String::from_utf8(data)?
+    };
+
+    let segments = if segmented { Some(chunks.len()) } else { None };
+    DecodedMessage { offset, chunk: chunks.swap_remove(0), segments, message }
+}
+
+/// Prints the message envelope hidden in the pixels by `encode_lsb`.
+#[throws]
+fn decode_lsb(args: DecodeArgs) {
+    if args.chunk_type.is_some() || args.keyword.is_some() || args.segmented {
+        throw!("a chunk type, --keyword and --segmented only apply to --mode chunk");
+    }
+
+    let reader = PngReader::new(BufReader::new(File::open(&args.file)?))?;
+    let image = Png::from_chunks(reader.collect::<Result<_, _>>()?).image()?;
+    let mut embedding = Embedding::new(image.header(), args.bits, &lsb_channels(&args.channels))?;
+    if let Some(passphrase) = &args.passphrase {
+        embedding = embedding.scattered(passphrase)?;
+    }
+
+    let header = embedding.extract(&image, Envelope::HEADER_LENGTH)?;
+    if !Envelope::is_envelope(&header) {
+        throw!("no message found in the pixels");
+    }
+    let data = embedding.extract(&image, Envelope::declared_length(&header)?)?;
+
+    let identity = args.identity.as_deref().map(keys::load_private_key).transpose()?;
+    let message = match message::unpack(std::iter::once(&data[..]), &decryption(&args.passphrase, &identity))? {
+        (ContentType::Text, body) => String::from_utf8(body)?,
+        (ContentType::Binary, body) => hex::encode(body),
+    };
+
+    match args.format {
+        Format::Text => println!("{}", message),
+        Format::Json => {
+            #[derive(serde::Serialize)]
+            struct Decoded<'a> {
+                bits: u8,
+                capacity: usize,
+                length: usize,
+                message: &'a str,
+            }
+
+            let decoded = Decoded {
+                bits: args.bits,
+                capacity: embedding.capacity(image.header()),
+                length: data.len(),
+                message: &message,
+            };
+            println!("{}", serde_json::to_string_pretty(&decoded)?);
+        }
+    }
+}
+
+/// The key `decode` was given, if any.
+fn decryption<'a>(passphrase: &'a Option<String>, identity: &'a Option<StaticSecret>) -> Decryption<'a> {
+    match (passphrase, identity) {
+        (Some(passphrase), _) => Decryption::Passphrase(passphrase),
+        (None, Some(identity)) => Decryption::Identity(identity),
+        (None, None) => Decryption::None,
+    }
+}
+
+/// Removes a chunk of `args.chunk_type` and rewrites the file.
+#[throws]
+pub fn remove(args: RemoveArgs) {
+    let mut editor = PngEditor::open(BufReader::new(File::open(&args.file)?))?;
+    editor.remove_chunk(&args.chunk_type.to_string())?;
+    write_atomically(&args.file, |writer| editor.write_to(writer))?;
+}
+
+/// Lists every chunk in the file, along with the decoded contents of
+/// the chunk types this crate knows.
+/// Chunks with invalid types or CRCs are shown rather than rejected.
+#[throws]
+pub fn print(args: PrintArgs) {
+    let reader = PngReader::new(BufReader::new(File::open(&args.file)?))?.lenient();
+    let png = Png::from_chunks(reader.collect::<Result<_, _>>()?);
+
+    match args.format {
+        Format::Text => println!("{}", png),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&png)?),
+    }
+}
+
+/// Prints every ordering or multiplicity rule the file breaks,
+/// failing if there is at least one.
+#[throws]
+pub fn validate(args: ValidateArgs) {
+    let reader = PngReader::new(BufReader::new(File::open(&args.file)?))?;
+    let png = Png::from_chunks(reader.collect::<Result<_, _>>()?);
+
+    if let Err(violations) = png.validate() {
+        for violation in violations.iter() {
+            println!("{}", violation);
+        }
+        throw!(format!("{} violation(s) found", violations.len()));
+    }
+}
+
+/// Writes a new private key to `args.output` and its public key next to
+/// it, printing the public key.
+#[throws]
+pub fn keygen(args: KeygenArgs) {
+    let public = if args.signing {
+        keys::generate_signing_key_pair(&args.output)?.to_bytes()
+    }
+    else {
+        keys::generate_key_pair(&args.output)?.to_bytes()
+    };
+    println!("{}", hex::encode(public));
+}
+
+/// Signs the critical chunks and the `args.include` chunk types, storing
+/// the signature in a siGN chunk before IEND. An existing signature is replaced.
+#[throws]
+pub fn sign(args: SignArgs) {
+    let key = keys::load_signing_key(&args.key)?;
+    let reader = PngReader::new(BufReader::new(File::open(&args.file)?))?;
+    let mut png = Png::from_chunks(reader.collect::<Result<_, _>>()?);
+
+    let chunk_type = ChunkType { bytes: SignatureChunk::CHUNK_TYPE }.to_string();
+    while png.remove_chunk(&chunk_type).is_ok() {}
+
+    let signature = SignatureChunk::sign(&png, &args.include, &key)?;
+    png.insert_chunk(signature.to_chunk(), &Position::BeforeIend)?;
+
+    let output = args.output.unwrap_or(args.file);
+    write_atomically(&output, |writer| png.write_to(writer))?;
+}
+
+/// Checks the siGN chunk of the file against `args.key`, failing if the
+/// file is unsigned or the signature does not match.
+#[throws]
+pub fn verify(args: VerifyArgs) {
+    let key = keys::load_verifying_key(&args.key)?;
+    let reader = PngReader::new(BufReader::new(File::open(&args.file)?))?;
+    let png = Png::from_chunks(reader.collect::<Result<_, _>>()?);
+
+    let chunk_type = ChunkType { bytes: SignatureChunk::CHUNK_TYPE }.to_string();
+    let signature = match png.chunk_by_type(&chunk_type) {
+        Some(chunk) => SignatureChunk::try_from(chunk)?,
+        None => throw!(PngError::ChunkNotFound(chunk_type)),
+    };
+    signature.verify(&png, &key)?;
+
+    let covered: Vec<String> = signature.covered().iter().map(ChunkType::to_string).collect();
+    if covered.is_empty() {
+        println!("valid signature over the critical chunks");
+    }
+    else {
+        println!("valid signature over the critical chunks and {}", covered.join(", "));
+    }
+}
+
+/// The chunk type `--mode chunk` needs.
+#[throws]
+fn required_chunk_type(chunk_type: &Option<ChunkType>) -> &ChunkType {
+    match chunk_type {
+        Some(chunk_type) => chunk_type,
+        None => throw!("a chunk type is required with --mode chunk"),
+    }
+}
+
+/// Keywords only apply to textual chunks.
+#[throws]
+fn check_text_chunk_type(chunk_type: &ChunkType) {
+    if !TextEntry::is_textual(chunk_type) {
+        throw!(format!("--keyword requires a textual chunk type (tEXt, zTXt or iTXt), got {}", chunk_type));
+    }
+}
+
+/// Builds the textual entry `encode` was asked to store.
+#[throws]
+fn text_entry(args: &EncodeArgs, chunk_type: &ChunkType, keyword: &str) -> TextEntry {
+    if args.language.is_none() && args.translated_keyword.is_none() && !args.compress {
+        TextEntry::new(chunk_type, keyword, &args.message)?
+    }
+    else {
+        if chunk_type.bytes() != &InternationalTextChunk::CHUNK_TYPE {
+            throw!(format!("--language, --translated-keyword and --compress require the iTXt chunk type, got {}", chunk_type));
+        }
+
+        let language = args.language.as_deref().unwrap_or("");
+        let translated_keyword = args.translated_keyword.as_deref().unwrap_or("");
+        let text = InternationalTextChunk::new(keyword, &args.message)?.with_language(language, translated_keyword)?;
+        TextEntry::International(if args.compress { text.compressed() } else { text })
+    }
+}
+
+/// Writes the output of `write` to a temporary file next to `path` and
+/// then renames it over `path`, so readers never observe a partially
+/// written file.
+#[throws]
+fn write_atomically<F>(path: &Path, write: F)
+where
+    F: FnOnce(BufWriter<File>) -> Result<BufWriter<File>, PngError>,
+{
+    let tmp_path = temporary_path(path);
+
+    if let Err(e) = write_file(&tmp_path, write).and_then(|_| Ok(fs::rename(&tmp_path, path)?)) {
+        let _ = fs::remove_file(&tmp_path);
+        throw!(e);
+    }
+}
+
+#[throws]
+fn write_file<F>(path: &Path, write: F)
+where
+    F: FnOnce(BufWriter<File>) -> Result<BufWriter<File>, PngError>,
+{
+    let writer = write(BufWriter::new(File::create(path)?))?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = std::ffi::OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{Cli, Command};
+    use clap::Parser;
+    use crate::text::TextChunk;
+    use std::str::FromStr;
+
+    fn read_png(path: &Path) -> Png {
+        Png::try_from(&fs::read(path).unwrap()[..]).unwrap()
+    }
+
+    fn testing_png_bytes() -> Vec<u8> {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    fn testing_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("png-msg-{}-{}.png", name, std::process::id()));
+        fs::write(&path, testing_png_bytes()).unwrap();
+        path
+    }
+
+    fn encode_args(file: &Path, chunk_type: &str, message: &str) -> EncodeArgs {
+        EncodeArgs {
+            file: file.to_path_buf(),
+            positionals: vec![],
+            chunk_type: Some(ChunkType::from_str(chunk_type).unwrap()),
+            message: String::from(message),
+            output: None,
+            before_idat: false,
+            after: None,
+            keyword: None,
+            language: None,
+            translated_keyword: None,
+            compress: false,
+            passphrase: None,
+            recipient: vec![],
+            max_chunk_size: None,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+        }
+    }
+
+    #[test]
+    fn test_encode_inserts_before_iend() {
+        let file = testing_file("encode");
+        encode(encode_args(&file, "ruSt", "hello")).unwrap();
+
+        let png = read_png(&file);
+        fs::remove_file(&file).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+        let data = png.chunk_by_type("ruSt").unwrap().data();
+        assert_eq!(message::unpack(vec![data], &Decryption::None).unwrap(), (ContentType::Text, b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_encode_before_idat() {
+        let file = testing_file("encode-before-idat");
+        encode(EncodeArgs { before_idat: true, ..encode_args(&file, "ruSt", "hello") }).unwrap();
+
+        let png = read_png(&file);
+        fs::remove_file(&file).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "ruSt", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_encode_to_output_file() {
+        let file = testing_file("encode-output");
+        let output = file.with_extension("out.png");
+        encode(EncodeArgs { output: Some(output.clone()), ..encode_args(&file, "ruSt", "hello") }).unwrap();
+
+        assert_eq!(fs::read(&file).unwrap(), testing_png_bytes());
+        assert!(read_png(&output).chunk_by_type("ruSt").is_some());
+        fs::remove_file(&file).unwrap();
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_remove() {
+        let file = testing_file("remove");
+        encode(encode_args(&file, "ruSt", "hello")).unwrap();
+
+        remove(RemoveArgs {
+            file: file.clone(),
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+        }).unwrap();
+
+        assert_eq!(fs::read(&file).unwrap(), testing_png_bytes());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_remove_missing_chunk() {
+        let file = testing_file("remove-missing");
+        let result = remove(RemoveArgs {
+            file: file.clone(),
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+        });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read(&file).unwrap(), testing_png_bytes());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_decode_missing_chunk() {
+        let file = testing_file("decode-missing");
+        let result = decode(DecodeArgs {
+            file: file.clone(),
+            chunk_type: Some(ChunkType::from_str("ruSt").unwrap()),
+            keyword: None,
+            passphrase: None,
+            identity: None,
+            segmented: false,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+            format: Format::Text,
+        });
+        fs::remove_file(&file).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_encode_rejects_reserved_chunk_type() {
+        let file = testing_file("encode-reserved");
+        let result = encode(encode_args(&file, "Rust", "hello"));
+        fs::remove_file(&file).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_encode_rejects_critical_chunk_type() {
+        let file = testing_file("encode-critical");
+        let result = encode(encode_args(&file, "IDAT", "hello"));
+
+        assert!(matches!(result, Err(e) if e.to_string().contains("critical")));
+        assert_eq!(fs::read(&file).unwrap(), testing_png_bytes());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_encode_text_replaces_keyword() {
+        let file = testing_file("encode-text");
+        let text_args = |keyword: &str, message: &str| EncodeArgs {
+            keyword: Some(String::from(keyword)),
+            ..encode_args(&file, "tEXt", message)
+        };
+        encode(text_args("Title", "Dice")).unwrap();
+        encode(text_args("Author", "Me")).unwrap();
+        encode(text_args("Title", "Café")).unwrap();
+
+        let png = read_png(&file);
+        fs::remove_file(&file).unwrap();
+
+        let texts: Vec<TextChunk> = png.chunks().iter()
+            .filter(|c| c.chunk_type().to_string() == "tEXt")
+            .map(|c| TextChunk::try_from(c).unwrap())
+            .collect();
+        assert_eq!(texts, [TextChunk::new("Author", "Me").unwrap(), TextChunk::new("Title", "Café").unwrap()]);
+    }
+
+    #[test]
+    fn test_keyword_skips_malformed_text() {
+        let file = testing_file("malformed-text");
+        let mut png = read_png(&file);
+        png.insert_chunk(Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"no separator".to_vec()), &Position::BeforeIend).unwrap();
+        fs::write(&file, png.as_bytes()).unwrap();
+
+        let text_args = |message: &str| EncodeArgs { keyword: Some(String::from("Title")), ..encode_args(&file, "tEXt", message) };
+        encode(text_args("Dice")).unwrap();
+        encode(text_args("Café")).unwrap();
+        let decoded = decode_chunks(&DecodeArgs {
+            file: file.clone(),
+            chunk_type: Some(ChunkType::from_str("tEXt").unwrap()),
+            keyword: Some(String::from("Title")),
+            passphrase: None,
+            identity: None,
+            segmented: false,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+            format: Format::Text,
+        }).unwrap();
+        let png = read_png(&file);
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(decoded.message, "Café");
+        let texts: Vec<&[u8]> = png.chunks().iter().filter(|c| c.chunk_type().to_string() == "tEXt").map(Chunk::data).collect();
+        assert_eq!(texts, [&b"no separator"[..], b"Title\0Caf\xe9"]);
+    }
+
+    #[test]
+    fn test_keyword_requires_text_chunk_type() {
+        let file = testing_file("encode-keyword-type");
+        let result = encode(EncodeArgs { keyword: Some(String::from("Title")), ..encode_args(&file, "ruSt", "hello") });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read(&file).unwrap(), testing_png_bytes());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_decode_missing_keyword() {
+        let file = testing_file("decode-keyword");
+        encode(EncodeArgs { keyword: Some(String::from("Title")), ..encode_args(&file, "tEXt", "Dice") }).unwrap();
+
+        let result = decode(DecodeArgs {
+            file: file.clone(),
+            chunk_type: Some(ChunkType::from_str("tEXt").unwrap()),
+            keyword: Some(String::from("Author")),
+            passphrase: None,
+            identity: None,
+            segmented: false,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+            format: Format::Json,
+        });
+        fs::remove_file(&file).unwrap();
+
+        assert!(matches!(result, Err(e) if e.to_string().contains("Author")));
+    }
+
+    #[test]
+    fn test_decode_json_matches_print() {
+        let file = testing_file("decode-json");
+        encode(encode_args(&file, "ruSt", "hello")).unwrap();
+        let decoded = decode_chunks(&DecodeArgs {
+            file: file.clone(),
+            chunk_type: Some(ChunkType::from_str("ruSt").unwrap()),
+            keyword: None,
+            passphrase: None,
+            identity: None,
+            segmented: false,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+            format: Format::Json,
+        }).unwrap();
+        let png = read_png(&file);
+        fs::remove_file(&file).unwrap();
+
+        let decoded = serde_json::to_value(&decoded).unwrap();
+        let printed = serde_json::to_value(&png).unwrap();
+        let entry = printed["chunks"].as_array().unwrap().iter().find(|c| c["type"] == decoded["type"]).unwrap();
+        for (field, value) in entry.as_object().unwrap() {
+            assert_eq!(&decoded[field], value, "{}", field);
+        }
+        assert_eq!(decoded["message"], "hello");
+    }
+
+    #[test]
+    fn test_encode_compressed_text_replaces_plain_text() {
+        let file = testing_file("encode-ztxt");
+        encode(EncodeArgs { keyword: Some(String::from("Title")), ..encode_args(&file, "tEXt", "Dice") }).unwrap();
+        encode(EncodeArgs { keyword: Some(String::from("Title")), ..encode_args(&file, "zTXt", "Würfel") }).unwrap();
+
+        let png = read_png(&file);
+        fs::remove_file(&file).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "IDAT", "zTXt", "IEND"]);
+        assert_eq!(TextEntry::try_from(png.chunk_by_type("zTXt").unwrap()).unwrap().text(), "Würfel");
+    }
+
+    #[test]
+    fn test_encode_international_text() {
+        let file = testing_file("encode-itxt");
+        encode(EncodeArgs {
+            keyword: Some(String::from("Title")),
+            language: Some(String::from("de")),
+            compress: true,
+            ..encode_args(&file, "iTXt", "Würfel ☃")
+        }).unwrap();
+
+        let png = read_png(&file);
+        fs::remove_file(&file).unwrap();
+
+        let expected = InternationalTextChunk::new("Title", "Würfel ☃").unwrap()
+            .with_language("de", "").unwrap()
+            .compressed();
+        assert_eq!(InternationalTextChunk::try_from(png.chunk_by_type("iTXt").unwrap()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_language_requires_international_text() {
+        let file = testing_file("encode-language");
+        let result = encode(EncodeArgs {
+            keyword: Some(String::from("Title")),
+            language: Some(String::from("de")),
+            ..encode_args(&file, "tEXt", "Würfel")
+        });
+        fs::remove_file(&file).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_encode_with_passphrase() {
+        let file = testing_file("encode-passphrase");
+        encode(EncodeArgs { passphrase: Some(String::from("secret")), ..encode_args(&file, "ruSt", "hello") }).unwrap();
+
+        let data = read_png(&file).chunk_by_type("ruSt").unwrap().data().to_vec();
+        assert!(!data.windows(5).any(|w| w == b"hello"));
+        assert_eq!(message::unpack(vec![&data[..]], &Decryption::Passphrase("secret")).unwrap().1, b"hello");
+
+        let decode_args = |passphrase: &str| DecodeArgs {
+            file: file.clone(),
+            chunk_type: Some(ChunkType::from_str("ruSt").unwrap()),
+            keyword: None,
+            passphrase: Some(String::from(passphrase)),
+            identity: None,
+            segmented: false,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+            format: Format::Text,
+        };
+        assert!(decode(decode_args("secret")).is_ok());
+        let result = decode(decode_args("wrong"));
+        fs::remove_file(&file).unwrap();
+
+        assert!(matches!(result, Err(e) if e.to_string().contains("wrong passphrase")));
+    }
+
+    #[test]
+    fn test_encode_to_recipient() {
+        let file = testing_file("encode-recipient");
+        let alice = file.with_extension("alice.key");
+        let eve = file.with_extension("eve.key");
+        keygen(KeygenArgs { output: alice.clone(), signing: false }).unwrap();
+        keygen(KeygenArgs { output: eve.clone(), signing: false }).unwrap();
+
+        encode(EncodeArgs { recipient: vec![keys::public_key_path(&alice)], ..encode_args(&file, "ruSt", "hello") }).unwrap();
+
+        let decode_args = |identity: &Path| DecodeArgs {
+            file: file.clone(),
+            chunk_type: Some(ChunkType::from_str("ruSt").unwrap()),
+            keyword: None,
+            passphrase: None,
+            identity: Some(identity.to_path_buf()),
+            segmented: false,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+            format: Format::Text,
+        };
+        let as_alice = decode(decode_args(&alice));
+        let as_eve = decode(decode_args(&eve));
+        let with_public_key = decode(decode_args(&keys::public_key_path(&alice)));
+        let to_private_key = encode(EncodeArgs { recipient: vec![alice.clone()], ..encode_args(&file, "ruSt", "hello") });
+
+        for path in [&file, &alice, &eve, &keys::public_key_path(&alice), &keys::public_key_path(&eve)].iter() {
+            fs::remove_file(path).unwrap();
+        }
+        assert!(as_alice.is_ok());
+        assert!(matches!(as_eve, Err(e) if e.to_string().contains("not encrypted to this key")));
+        assert!(matches!(with_public_key, Err(e) if e.to_string().contains("wrong key type")));
+        assert!(matches!(to_private_key, Err(e) if e.to_string().contains("wrong key type")));
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let file = testing_file("sign");
+        let key = file.with_extension("sign.key");
+        keygen(KeygenArgs { output: key.clone(), signing: true }).unwrap();
+        let public_key = keys::public_key_path(&key);
+
+        let sign_args = || SignArgs { file: file.clone(), key: key.clone(), include: vec![], output: None };
+        sign(sign_args()).unwrap();
+        sign(sign_args()).unwrap();
+        let verify_args = || VerifyArgs { file: file.clone(), key: public_key.clone() };
+        let signed = verify(verify_args());
+
+        encode(encode_args(&file, "ruSt", "hello")).unwrap();
+        let with_message = verify(verify_args());
+
+        let mut png = read_png(&file);
+        png.remove_chunk("IDAT").unwrap();
+        fs::write(&file, png.as_bytes()).unwrap();
+        let tampered = verify(verify_args());
+
+        let x25519 = file.with_extension("x25519.key");
+        keygen(KeygenArgs { output: x25519.clone(), signing: false }).unwrap();
+        let sign_with_x25519 = sign(SignArgs { key: x25519.clone(), ..sign_args() });
+        let verify_with_x25519 = verify(VerifyArgs { key: keys::public_key_path(&x25519), ..verify_args() });
+        let verify_with_signing_key = verify(VerifyArgs { key: key.clone(), ..verify_args() });
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        for path in [&file, &key, &public_key, &x25519, &keys::public_key_path(&x25519)].iter() {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(types, ["IHDR", "siGN", "ruSt", "IEND"]);
+        for result in [sign_with_x25519, verify_with_x25519, verify_with_signing_key] {
+            assert!(matches!(result, Err(e) if e.to_string().contains("wrong key type")));
+        }
+        assert!(signed.is_ok());
+        assert!(with_message.is_ok());
+        assert!(tampered.is_err());
+    }
+
+    #[test]
+    fn test_encode_segmented() {
+        let file = testing_file("encode-segmented");
+        encode(EncodeArgs {
+            max_chunk_size: Some(Envelope::HEADER_LENGTH + segment::HEADER_LENGTH + 4),
+            before_idat: true,
+            ..encode_args(&file, "ruSt", "hello world")
+        }).unwrap();
+
+        let png = read_png(&file);
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "ruSt", "ruSt", "ruSt", "IDAT", "IEND"]);
+
+        let decode_args = |segmented: bool| DecodeArgs {
+            file: file.clone(),
+            chunk_type: Some(ChunkType::from_str("ruSt").unwrap()),
+            keyword: None,
+            passphrase: None,
+            identity: None,
+            segmented,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+            format: Format::Text,
+        };
+        assert!(decode(decode_args(false)).is_ok());
+
+        let mut png = png;
+        png.remove_chunk("ruSt").unwrap();
+        fs::write(&file, png.as_bytes()).unwrap();
+        let incomplete = decode(decode_args(false));
+        fs::remove_file(&file).unwrap();
+
+        assert!(matches!(incomplete, Err(e) if e.to_string().contains("found 2 of 3 segments")));
+    }
+
+    #[test]
+    fn test_decode_keyword_like_envelope() {
+        let file = testing_file("decode-pmsg-keyword");
+        let path = file.to_str().unwrap();
+        run(&["encode", path, "tEXt", "hello", "--keyword", "PMSG title"]).unwrap();
+        let decoded = run(&["decode", path, "tEXt"]);
+        fs::remove_file(&file).unwrap();
+
+        assert!(decoded.is_ok());
+    }
+
+    #[test]
+    fn test_decode_raw_messages() {
+        let file = testing_file("decode-raw");
+        let mut png = read_png(&file);
+        for data in segment::split(b"hello world", 8).unwrap() {
+            png.insert_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), data), &Position::BeforeIend).unwrap();
+        }
+        fs::write(&file, png.as_bytes()).unwrap();
+
+        let decode_args = |segmented| DecodeArgs {
+            file: file.clone(),
+            chunk_type: Some(ChunkType::from_str("ruSt").unwrap()),
+            keyword: None,
+            passphrase: None,
+            identity: None,
+            segmented,
+            mode: Mode::Chunk,
+            bits: 1,
+            channels: vec![],
+            format: Format::Text,
+        };
+        let joined = decode_chunks(&decode_args(true)).unwrap();
+        let unjoined = decode_chunks(&decode_args(false)).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(joined.message, "hello world");
+        assert_eq!(joined.segments, Some(3));
+        assert_eq!(joined.offset, 8 + 25 + 15);
+        assert_eq!(unjoined.message, "\0\u{1}\0\u{3}hell");
+        assert_eq!(unjoined.segments, None);
+    }
+
+    /// A file with real pixels, 16x16 RGBA, and a text chunk after the image data.
+    fn testing_image_file(name: &str) -> PathBuf {
+        use crate::ihdr::{ColorType, Ihdr, Interlace};
+
+        let header = Ihdr::new(16, 16, 8, ColorType::Rgba, Interlace::Adam7).unwrap();
+        let image = Image::new(header, (0..16 * 16 * 4).map(|i| (i * 5 % 256) as u8).collect()).unwrap();
+        let mut png = image.to_png(FilterStrategy::Adaptive, 256).unwrap();
+        png.insert_chunk(TextChunk::new("Title", "Dice").unwrap().to_chunk(), &Position::BeforeIend).unwrap();
+
+        let path = std::env::temp_dir().join(format!("png-msg-{}-{}.png", name, std::process::id()));
+        fs::write(&path, png.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_lsb_round_trip() {
+        let file = testing_image_file("lsb");
+        let path = file.to_str().unwrap();
+        let original = read_png(&file);
+        run(&["encode", path, "--mode", "lsb", "hello pixels", "--bits", "2", "--passphrase", "secret"]).unwrap();
+
+        let png = read_png(&file);
+        let decoded = run(&["decode", path, "--mode", "lsb", "--bits", "2", "--passphrase", "secret"]);
+        let wrong_passphrase = run(&["decode", path, "--mode", "lsb", "--bits", "2", "--passphrase", "guess"]);
+        let wrong_bits = run(&["decode", path, "--mode", "lsb", "--passphrase", "secret"]);
+        fs::remove_file(&file).unwrap();
+
+        assert!(decoded.is_ok());
+        assert!(wrong_passphrase.is_err());
+        assert!(matches!(wrong_bits, Err(e) if e.to_string().contains("no message found")));
+
+        let types = |png: &Png| png.chunks().iter().map(|c| c.chunk_type().to_string()).collect::<Vec<_>>();
+        assert_eq!(types(&png).iter().filter(|t| *t != "IDAT").count(), 3);
+        assert_eq!(png.chunk_by_type("tEXt").unwrap().data(), original.chunk_by_type("tEXt").unwrap().data());
+        assert_eq!(png.header_info().unwrap(), original.header_info().unwrap());
+        assert_ne!(png.image().unwrap(), original.image().unwrap());
+    }
+
+    #[test]
+    fn test_lsb_drops_signature() {
+        let file = testing_image_file("lsb-signed");
+        let key = file.with_extension("sign.key");
+        keygen(KeygenArgs { output: key.clone(), signing: true }).unwrap();
+        sign(SignArgs { file: file.clone(), key: key.clone(), include: vec![], output: None }).unwrap();
+        let signed = read_png(&file);
+
+        run(&["encode", file.to_str().unwrap(), "--mode", "lsb", "hello pixels"]).unwrap();
+        let png = read_png(&file);
+        for path in [&file, &key, &keys::public_key_path(&key)].iter() {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert!(signed.chunk_by_type("siGN").is_some());
+        assert!(png.chunk_by_type("siGN").is_none());
+        assert!(png.chunk_by_type("tEXt").is_some());
+    }
+
+    #[test]
+    fn test_lsb_rejects_chunk_options() {
+        let file = testing_image_file("lsb-options");
+        let path = file.to_str().unwrap();
+        let keyword = run(&["encode", path, "--mode", "lsb", "hello", "--keyword", "Comment"]);
+        let chunk_type = run(&["encode", path, "--mode", "lsb", "ruSt", "hello", "extra"]);
+        let too_long = run(&["encode", path, "--mode", "lsb", &"a".repeat(200)]);
+        let decode_chunk_type = run(&["decode", path, "ruSt", "--mode", "lsb"]);
+        let missing_chunk_type = run(&["decode", path]);
+        let missing_message = run(&["encode", path, "ruSt"]);
+        fs::remove_file(&file).unwrap();
+
+        assert!(matches!(keyword, Err(e) if e.to_string().contains("only apply to --mode chunk")));
+        assert!(matches!(chunk_type, Err(e) if e.to_string().contains("CHUNK_TYPE is not used")));
+        assert!(matches!(decode_chunk_type, Err(e) if e.to_string().contains("CHUNK_TYPE is not used")));
+        assert!(matches!(missing_chunk_type, Err(e) if e.to_string().contains("CHUNK_TYPE is required")));
+        assert!(matches!(missing_message, Err(e) if e.to_string().contains("MESSAGE is required")));
+        assert!(matches!(too_long, Err(e) if e.to_string().contains("do not fit")));
+    }
+
+    /// Parses `args` as the command line would and runs one of the
+    /// encoding or decoding commands.
+    fn run(args: &[&str]) -> Result<(), Error> {
+        match Cli::try_parse_from(std::iter::once("png-msg").chain(args.iter().copied()))?.resolved()?.command {
+            Command::Encode(args) => encode(args),
+            Command::Decode(args) => decode(args),
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_textual_chunks_require_keyword() {
+        let file = testing_file("textual-keyword");
+        let path = file.to_str().unwrap();
+        let plain = run(&["encode", path, "tEXt", "hello world"]);
+        let encrypted = run(&["encode", path, "tEXt", "hello", "--passphrase", "pw"]);
+        let png = read_png(&file);
+        let keyword = run(&["encode", path, "tEXt", "hello", "--keyword", "Comment"]);
+        fs::remove_file(&file).unwrap();
+
+        assert!(matches!(plain, Err(e) if e.to_string().contains("--keyword is required")));
+        assert!(encrypted.is_err());
+        assert!(png.chunk_by_type("tEXt").is_none());
+        assert!(keyword.is_ok());
+    }
+
+    #[test]
+    fn test_textual_chunks_reject_decryption_options() {
+        let file = testing_file("textual-decode");
+        let path = file.to_str().unwrap();
+        run(&["encode", path, "tEXt", "hello", "--keyword", "Comment"]).unwrap();
+
+        let plain = run(&["decode", path, "tEXt"]);
+        let passphrase = run(&["decode", path, "tEXt", "--passphrase", "pw"]);
+        let identity = run(&["decode", path, "zTXt", "--identity", "key"]);
+        let segmented = run(&["decode", path, "iTXt", "--segmented"]);
+        fs::remove_file(&file).unwrap();
+
+        assert!(plain.is_ok());
+        for result in [passphrase, identity, segmented] {
+            assert!(matches!(result, Err(e) if e.to_string().contains("never encrypted or segmented")));
+        }
+    }
+}
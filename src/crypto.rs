@@ -0,0 +1,258 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use crate::error::PngError;
+
+/// Length of the random salt fed to the key derivation function.
+pub const SALT_LENGTH: usize = 16;
+
+/// Length of the ChaCha20-Poly1305 nonce.
+pub const NONCE_LENGTH: usize = 12;
+
+/// Length of the Poly1305 authentication tag appended to the ciphertext.
+pub const TAG_LENGTH: usize = 16;
+
+/// Length of a recipient stanza: an ephemeral public key followed by
+/// the wrapped file key and its tag.
+pub const STANZA_LENGTH: usize = 32 + 32 + TAG_LENGTH;
+
+/// Argon2id cost parameters: memory in KiB, passes and lanes. Messages
+/// store only the salt, so these are part of the format and must never
+/// change, whatever defaults the argon2 crate moves to.
+const ARGON2_MEMORY_COST: u32 = 19 * 1024;
+const ARGON2_TIME_COST: u32 = 2;
+const ARGON2_PARALLELISM: u32 = 1;
+
+/// Context string for deriving the key that wraps the file key.
+const STANZA_INFO: &[u8] = b"png-msg/X25519";
+
+/// Encrypts `plaintext` with a key derived from `passphrase`.
+///
+/// The output is the salt, the nonce and the authenticated ciphertext,
+/// in that order, so it can be decrypted with nothing but the passphrase.
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>, PngError> {
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher.encrypt(&nonce, plaintext)
+        .map_err(|_| PngError::Encryption(String::from("encryption failed")))?;
+
+    let mut output = Vec::with_capacity(SALT_LENGTH + NONCE_LENGTH + ciphertext.len());
+    output.extend_from_slice(&salt);
+    output.extend_from_slice(&nonce);
+    output.extend(ciphertext);
+    Ok(output)
+}
+
+/// Decrypts the output of `encrypt`, failing if the passphrase is wrong
+/// or the data was modified.
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, PngError> {
+    if data.len() < SALT_LENGTH + NONCE_LENGTH + TAG_LENGTH {
+        return Err(PngError::Encryption(format!("encrypted message is too short ({} bytes)", data.len())));
+    }
+
+    let (salt, rest) = data.split_at(SALT_LENGTH);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| PngError::Encryption(String::from("wrong passphrase or the message was tampered with")))
+}
+
+/// Derives a 256-bit seed from `passphrase` with Argon2id, for uses that
+/// have nowhere to store a random salt. `context` takes the place of the
+/// salt, so seeds for different purposes are unrelated.
+pub fn derive_seed(passphrase: &str, context: &[u8]) -> Result<[u8; 32], PngError> {
+    Ok(derive_key(passphrase, context)?.into())
+}
+
+/// Stretches `passphrase` into a 256-bit key with Argon2id version 1.3
+/// and the pinned cost parameters.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, PngError> {
+    let mut key = Key::default();
+    let params = Params::new(ARGON2_MEMORY_COST, ARGON2_TIME_COST, ARGON2_PARALLELISM, Some(key.len()))
+        .map_err(|e| PngError::Encryption(format!("invalid key derivation parameters: {}", e)))?;
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| PngError::Encryption(format!("key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+/// Encrypts `plaintext` so that any of `recipients` can decrypt it.
+///
+/// As in age, the message is encrypted with a random file key, and the
+/// file key is wrapped once per recipient in a stanza holding a fresh
+/// ephemeral public key. The output is the number of stanzas as a byte,
+/// the stanzas, the nonce and the authenticated ciphertext.
+pub fn encrypt_to_recipients(plaintext: &[u8], recipients: &[PublicKey]) -> Result<Vec<u8>, PngError> {
+    if recipients.is_empty() || recipients.len() > u8::MAX as usize {
+        return Err(PngError::Encryption(format!("expected 1 to 255 recipients, got {}", recipients.len())));
+    }
+
+    let file_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut output = vec![recipients.len() as u8];
+
+    for recipient in recipients.iter() {
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(recipient);
+        if !shared.was_contributory() {
+            return Err(PngError::Encryption(String::from("recipient public key is a low order point")));
+        }
+
+        let wrapping_key = stanza_key(shared.as_bytes(), &ephemeral_public, recipient);
+        let wrapped = ChaCha20Poly1305::new(&wrapping_key)
+            .encrypt(&Nonce::default(), file_key.as_slice())
+            .map_err(|_| PngError::Encryption(String::from("encryption failed")))?;
+
+        output.extend_from_slice(ephemeral_public.as_bytes());
+        output.extend(wrapped);
+    }
+
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = ChaCha20Poly1305::new(&file_key).encrypt(&nonce, plaintext)
+        .map_err(|_| PngError::Encryption(String::from("encryption failed")))?;
+    output.extend_from_slice(&nonce);
+    output.extend(ciphertext);
+    Ok(output)
+}
+
+/// Decrypts the output of `encrypt_to_recipients` with the private key
+/// of one of the recipients.
+pub fn decrypt_with_identity(data: &[u8], identity: &StaticSecret) -> Result<Vec<u8>, PngError> {
+    let (&count, rest) = data.split_first()
+        .ok_or_else(|| PngError::Encryption(String::from("encrypted message is empty")))?;
+    let stanzas_length = count as usize * STANZA_LENGTH;
+    if rest.len() < stanzas_length + NONCE_LENGTH + TAG_LENGTH {
+        return Err(PngError::Encryption(format!("encrypted message is too short ({} bytes)", data.len())));
+    }
+
+    let (stanzas, rest) = rest.split_at(stanzas_length);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+    let identity_public = PublicKey::from(identity);
+
+    let file_key = stanzas.chunks(STANZA_LENGTH)
+        .find_map(|stanza| {
+            let mut ephemeral_public = [0u8; 32];
+            ephemeral_public.copy_from_slice(&stanza[..32]);
+            let ephemeral_public = PublicKey::from(ephemeral_public);
+
+            let shared = identity.diffie_hellman(&ephemeral_public);
+            let wrapping_key = stanza_key(shared.as_bytes(), &ephemeral_public, &identity_public);
+            ChaCha20Poly1305::new(&wrapping_key).decrypt(&Nonce::default(), &stanza[32..]).ok()
+        })
+        .ok_or_else(|| PngError::Encryption(String::from("the message was not encrypted to this key")))?;
+
+    ChaCha20Poly1305::new(Key::from_slice(&file_key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| PngError::Encryption(String::from("the message was tampered with")))
+}
+
+/// Derives the key that wraps the file key in one stanza, binding it to
+/// both public keys of the exchange.
+fn stanza_key(shared_secret: &[u8; 32], ephemeral_public: &PublicKey, recipient: &PublicKey) -> Key {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral_public.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret)
+        .expand(STANZA_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_derivation_is_stable() {
+        let seed = derive_seed("correct horse", b"png-msg test salt").unwrap();
+        assert_eq!(hex::encode(seed), "cd556f1ef9b0dd9dc43c5bccc79d7f10115e4f325606d672b5fb4cc607acc367");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let encrypted = encrypt(b"hello", "correct horse").unwrap();
+
+        assert_eq!(encrypted.len(), SALT_LENGTH + NONCE_LENGTH + 5 + TAG_LENGTH);
+        assert!(!encrypted.windows(5).any(|w| w == b"hello"));
+        assert_eq!(decrypt(&encrypted, "correct horse").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_salt_and_nonce_are_random() {
+        assert_ne!(encrypt(b"hello", "pass").unwrap(), encrypt(b"hello", "pass").unwrap());
+    }
+
+    #[test]
+    fn test_derive_seed() {
+        let seed = derive_seed("pass", b"context one").unwrap();
+
+        assert_eq!(seed, derive_seed("pass", b"context one").unwrap());
+        assert_ne!(seed, derive_seed("pass", b"context two").unwrap());
+        assert_ne!(seed, derive_seed("other", b"context one").unwrap());
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let encrypted = encrypt(b"hello", "correct horse").unwrap();
+        assert!(matches!(decrypt(&encrypted, "battery staple"), Err(PngError::Encryption(_))));
+    }
+
+    #[test]
+    fn test_tampering() {
+        let mut encrypted = encrypt(b"hello", "pass").unwrap();
+        encrypted[SALT_LENGTH + NONCE_LENGTH] ^= 1;
+        assert!(decrypt(&encrypted, "pass").is_err());
+
+        assert!(decrypt(&encrypted[..SALT_LENGTH + NONCE_LENGTH + TAG_LENGTH - 1], "pass").is_err());
+    }
+
+    fn key_pair() -> (StaticSecret, PublicKey) {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+        (secret, public)
+    }
+
+    #[test]
+    fn test_recipients_round_trip() {
+        let (alice, alice_public) = key_pair();
+        let (bob, bob_public) = key_pair();
+        let encrypted = encrypt_to_recipients(b"hello", &[alice_public, bob_public]).unwrap();
+
+        assert_eq!(encrypted.len(), 1 + 2 * STANZA_LENGTH + NONCE_LENGTH + 5 + TAG_LENGTH);
+        assert_eq!(decrypt_with_identity(&encrypted, &alice).unwrap(), b"hello");
+        assert_eq!(decrypt_with_identity(&encrypted, &bob).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_not_a_recipient() {
+        let (_, alice_public) = key_pair();
+        let (eve, _) = key_pair();
+        let encrypted = encrypt_to_recipients(b"hello", &[alice_public]).unwrap();
+
+        let result = decrypt_with_identity(&encrypted, &eve);
+        assert!(matches!(result, Err(PngError::Encryption(reason)) if reason.contains("not encrypted to this key")));
+    }
+
+    #[test]
+    fn test_recipients_tampering() {
+        let (alice, alice_public) = key_pair();
+        let mut encrypted = encrypt_to_recipients(b"hello", &[alice_public]).unwrap();
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+
+        assert!(decrypt_with_identity(&encrypted, &alice).is_err());
+        assert!(decrypt_with_identity(&encrypted[..40], &alice).is_err());
+        assert!(encrypt_to_recipients(b"hello", &[]).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::convert::TryFrom;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::png::Png;
+use crate::validation::{insertion_index, Position};
+use crate::writer::PngWriter;
+
+/// Edits the chunk list of a PNG file without parsing the chunks into memory.
+///
+/// Opening the editor only records where each chunk lives in the source.
+/// When the result is written, chunks that were not touched are copied
+/// byte for byte from the source and only inserted chunks are serialized.
+pub struct PngEditor<R> {
+    source: R,
+    entries: Vec<Entry>,
+}
+
+/// The location of a chunk in the source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkSpan {
+    pub chunk_type: ChunkType,
+    /// Byte offset of the chunk's length field.
+    pub offset: u64,
+    /// Length of the chunk's data.
+    pub length: u32,
+}
+
+impl ChunkSpan {
+    /// Size of the whole chunk, including length, type and CRC.
+    pub fn size(&self) -> u64 {
+        self.length as u64 + 12
+    }
+}
+
+enum Entry {
+    Original(ChunkSpan),
+    Inserted(Chunk),
+}
+
+impl Entry {
+    fn chunk_type(&self) -> &ChunkType {
+        match self {
+            Entry::Original(span) => &span.chunk_type,
+            Entry::Inserted(chunk) => chunk.chunk_type(),
+        }
+    }
+}
+
+impl<R: Read + Seek> PngEditor<R> {
+    /// Checks the signature of `source` and records the position of every chunk.
+    /// Chunk data is seeked over, not read.
+    pub fn open(mut source: R) -> Result<Self, PngError> {
+        let end = source.seek(SeekFrom::End(0))?;
+        source.seek(SeekFrom::Start(0))?;
+
+        let mut signature = [0u8; 8];
+        if end < signature.len() as u64 {
+            return Err(PngError::InvalidSignature);
+        }
+        source.read_exact(&mut signature)?;
+        if signature != Png::STANDARD_HEADER {
+            return Err(PngError::InvalidSignature);
+        }
+
+        let mut entries = vec![];
+        let mut offset = signature.len() as u64;
+        while offset < end {
+            let span = read_span(&mut source, offset, end)
+                .map_err(|e| e.at_chunk(entries.len(), offset as usize))?;
+
+            offset += span.size();
+            source.seek(SeekFrom::Start(offset))?;
+            entries.push(Entry::Original(span));
+        }
+
+        Ok(PngEditor { source, entries })
+    }
+
+    /// Types of the chunks in the edited file, in order.
+    pub fn chunk_types(&self) -> impl DoubleEndedIterator<Item = &ChunkType> + ExactSizeIterator {
+        self.entries.iter().map(Entry::chunk_type)
+    }
+
+    /// Inserts `chunk` at `position`, moved as little as needed to keep
+    /// the ordering rules of the PNG specification.
+    #[cfg(test)]
+    pub fn insert_chunk(&mut self, chunk: Chunk, position: &Position) -> Result<(), PngError> {
+        let chunk_types: Vec<&ChunkType> = self.chunk_types().collect();
+        let index = insertion_index(&chunk_types, chunk.chunk_type(), position)?;
+        self.entries.insert(index, Entry::Inserted(chunk));
+        Ok(())
+    }
+
+    /// Inserts `chunks`, which must all have the same type, next to each
+    /// other and in order at `position`.
+    pub fn insert_chunks(&mut self, chunks: Vec<Chunk>, position: &Position) -> Result<(), PngError> {
+        let first_type = match chunks.first() {
+            Some(chunk) => chunk.chunk_type().clone(),
+            None => return Ok(()),
+        };
+        debug_assert!(chunks.iter().all(|c| c.chunk_type() == &first_type));
+
+        let chunk_types: Vec<&ChunkType> = self.chunk_types().collect();
+        let index = insertion_index(&chunk_types, &first_type, position)?;
+        self.entries.splice(index..index, chunks.into_iter().map(Entry::Inserted));
+        Ok(())
+    }
+
+    /// Removes the last chunk of type `chunk_type`.
+    pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<(), PngError> {
+        let index = self.chunk_types().rposition(|t| t.to_string() == chunk_type);
+
+        match index {
+            Some(i) => {
+                self.entries.remove(i);
+                Ok(())
+            }
+            None => Err(PngError::ChunkNotFound(chunk_type.to_string())),
+        }
+    }
+
+    /// Removes every chunk of type `chunk_type` for which `predicate`
+    /// returns `true`, returning how many were removed.
+    /// Only the data of chunks of that type is read from the source.
+    pub fn remove_chunks_where<F>(&mut self, chunk_type: &str, mut predicate: F) -> Result<usize, PngError>
+    where
+        F: FnMut(&Chunk) -> Result<bool, PngError>,
+    {
+        let mut removed = 0;
+        let mut i = 0;
+        while i < self.entries.len() {
+            let matches = match &self.entries[i] {
+                entry if entry.chunk_type().to_string() != chunk_type => false,
+                Entry::Original(span) => {
+                    let chunk = read_chunk(&mut self.source, span).map_err(|e| e.at_chunk(i, span.offset as usize))?;
+                    predicate(&chunk)?
+                }
+                Entry::Inserted(chunk) => predicate(chunk)?,
+            };
+
+            if matches {
+                self.entries.remove(i);
+                removed += 1;
+            }
+            else {
+                i += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Writes the edited file to `writer`, copying untouched chunks
+    /// directly from the source.
+    pub fn write_to<W: Write>(&mut self, writer: W) -> Result<W, PngError> {
+        let mut writer = PngWriter::new(writer)?;
+
+        for entry in self.entries.iter() {
+            match entry {
+                Entry::Original(span) => {
+                    self.source.seek(SeekFrom::Start(span.offset))?;
+                    let copied = io::copy(&mut (&mut self.source).take(span.size()), writer.get_mut())?;
+                    if copied < span.size() {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                    }
+                }
+                Entry::Inserted(chunk) => writer.write_chunk(chunk)?,
+            }
+        }
+
+        writer.finish()
+    }
+}
+
+
+/// Reads the length and type of the chunk at `offset` and checks that
+/// the whole chunk fits before `end`.
+fn read_span<R: Read>(source: &mut R, offset: u64, end: u64) -> Result<ChunkSpan, PngError> {
+    let available = (end - offset) as usize;
+    if available < 12 {
+        return Err(PngError::TruncatedChunk { needed: 12, available });
+    }
+
+    let mut header = [0u8; 8];
+    source.read_exact(&mut header)?;
+
+    let mut length = [0u8; 4];
+    length.copy_from_slice(&header[0..4]);
+    let length = u32::from_be_bytes(length);
+
+    let mut chunk_type = [0u8; 4];
+    chunk_type.copy_from_slice(&header[4..8]);
+    let chunk_type = ChunkType::try_from(chunk_type)?;
+
+    let span = ChunkSpan { chunk_type, offset, length };
+    if span.size() > available as u64 {
+        return Err(PngError::TruncatedChunk { needed: span.size() as usize, available });
+    }
+
+    Ok(span)
+}
+
+/// Reads and parses the chunk at `span`.
+fn read_chunk<R: Read + Seek>(source: &mut R, span: &ChunkSpan) -> Result<Chunk, PngError> {
+    source.seek(SeekFrom::Start(span.offset))?;
+    let mut bytes = Vec::new();
+    source.take(span.size()).read_to_end(&mut bytes)?;
+    Chunk::try_from(&bytes[..])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![42; 100]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ]
+    }
+
+    fn testing_bytes() -> Vec<u8> {
+        Png::from_chunks(testing_chunks()).as_bytes()
+    }
+
+    fn edited_bytes(editor: &mut PngEditor<Cursor<Vec<u8>>>) -> Vec<u8> {
+        editor.write_to(Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn test_open_records_spans() {
+        let editor = PngEditor::open(Cursor::new(testing_bytes())).unwrap();
+        let types: Vec<String> = editor.chunk_types().map(|t| t.to_string()).collect();
+
+        assert_eq!(types, ["IHDR", "IDAT", "IEND"]);
+        match &editor.entries[1] {
+            Entry::Original(span) => assert_eq!((span.offset, span.length), (8 + 25, 100)),
+            Entry::Inserted(_) => panic!("expected an original chunk"),
+        }
+    }
+
+    #[test]
+    fn test_unchanged_round_trip() {
+        let mut editor = PngEditor::open(Cursor::new(testing_bytes())).unwrap();
+        assert_eq!(edited_bytes(&mut editor), testing_bytes());
+    }
+
+    #[test]
+    fn test_insert_chunk() {
+        let mut editor = PngEditor::open(Cursor::new(testing_bytes())).unwrap();
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+        editor.insert_chunk(chunk, &Position::BeforeIend).unwrap();
+
+        let mut expected = testing_chunks();
+        expected.insert(2, Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec()));
+
+        assert_eq!(edited_bytes(&mut editor), Png::from_chunks(expected).as_bytes());
+    }
+
+    #[test]
+    fn test_insert_chunks_keeps_order() {
+        let mut editor = PngEditor::open(Cursor::new(testing_bytes())).unwrap();
+        let segments: Vec<Chunk> = (0..3).map(|i| Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![i])).collect();
+        editor.insert_chunks(segments, &Position::After(ChunkType::from_str("IHDR").unwrap())).unwrap();
+
+        let mut expected = testing_chunks();
+        for i in 0..3 {
+            expected.insert(1 + i as usize, Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![i]));
+        }
+
+        assert_eq!(edited_bytes(&mut editor), Png::from_chunks(expected).as_bytes());
+    }
+
+    #[test]
+    fn test_remove_chunk() {
+        let mut with_message = testing_chunks();
+        with_message.insert(2, Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec()));
+        let bytes = Png::from_chunks(with_message).as_bytes();
+
+        let mut editor = PngEditor::open(Cursor::new(bytes)).unwrap();
+        editor.remove_chunk("ruSt").unwrap();
+
+        assert_eq!(edited_bytes(&mut editor), testing_bytes());
+        assert!(matches!(editor.remove_chunk("ruSt"), Err(PngError::ChunkNotFound(_))));
+    }
+
+    #[test]
+    fn test_remove_chunks_where() {
+        let mut chunks = testing_chunks();
+        chunks.insert(2, Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"keep".to_vec()));
+        chunks.insert(3, Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"drop".to_vec()));
+        let bytes = Png::from_chunks(chunks).as_bytes();
+
+        let mut editor = PngEditor::open(Cursor::new(bytes)).unwrap();
+        let removed = editor.remove_chunks_where("ruSt", |c| Ok(c.data() == b"drop")).unwrap();
+
+        let mut expected = testing_chunks();
+        expected.insert(2, Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"keep".to_vec()));
+
+        assert_eq!(removed, 1);
+        assert_eq!(edited_bytes(&mut editor), Png::from_chunks(expected).as_bytes());
+    }
+
+    #[test]
+    fn test_untouched_chunks_are_copied_verbatim() {
+        let mut bytes = testing_bytes();
+        // Corrupt the IDAT CRC; the editor never looks at it.
+        let crc_index = 8 + 25 + 8 + 100;
+        bytes[crc_index] ^= 0xff;
+
+        let mut editor = PngEditor::open(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(edited_bytes(&mut editor), bytes);
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let bytes = testing_bytes();
+        let editor = PngEditor::open(Cursor::new(bytes[..bytes.len() - 20].to_vec()));
+
+        match editor {
+            Err(PngError::Chunk { index, source, .. }) => {
+                assert_eq!(index, 1);
+                assert!(matches!(*source, PngError::TruncatedChunk { needed: 112, .. }));
+            }
+            _ => panic!("expected a truncated chunk"),
+        }
+    }
+}
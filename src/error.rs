@@ -0,0 +1,200 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors produced while parsing or manipulating PNG data.
+#[derive(Debug)]
+pub enum PngError {
+    /// The data does not start with the 8-byte PNG signature.
+    InvalidSignature,
+
+    /// The input ended before the chunk did.
+    /// `needed` is the number of bytes the chunk requires and
+    /// `available` is how many were left.
+    TruncatedChunk { needed: usize, available: usize },
+
+    /// The chunk was given more bytes than its length field declares.
+    LengthMismatch { declared: usize, actual: usize },
+
+    /// The CRC stored in the chunk does not match its contents.
+    /// `expected` is computed from the chunk type and data,
+    /// `actual` is the value found in the chunk.
+    CrcMismatch { expected: u32, actual: u32 },
+
+    /// The chunk holds more than the 2^31 - 1 bytes of data PNG allows.
+    ChunkTooLarge(usize),
+
+    /// The chunk type contains bytes that are not ASCII letters.
+    InvalidChunkType([u8; 4]),
+
+    /// Chunk types must be exactly four bytes long.
+    InvalidChunkTypeLength(usize),
+
+    /// No chunk of the requested type exists.
+    ChunkNotFound(String),
+
+    /// The IHDR chunk is malformed or holds values the specification forbids.
+    InvalidIhdr(String),
+
+    /// A textual chunk (tEXt, zTXt or iTXt) is malformed or holds
+    /// values the specification forbids.
+    InvalidTextChunk(String),
+
+    /// The data of a known ancillary chunk does not follow its layout.
+    MalformedChunk { chunk_type: String, reason: String },
+
+    /// A message could not be encrypted or decrypted, most often because
+    /// the passphrase is wrong or the ciphertext was modified.
+    Encryption(String),
+
+    /// A key file is malformed.
+    InvalidKey(String),
+
+    /// An image signature is malformed or does not verify.
+    Signature(String),
+
+    /// The segments of a split message are missing, duplicated or out of order.
+    Segment(String),
+
+    /// A message envelope is malformed or uses a version or flags this
+    /// build does not support.
+    InvalidEnvelope(String),
+
+    /// A zlib stream could not be decompressed.
+    Zlib(String),
+
+    /// The decompressed IDAT data does not match the scanlines the IHDR
+    /// chunk describes, or uses an unknown filter type.
+    InvalidImageData(String),
+
+    /// The pixels of an image cannot carry a message as asked, or the
+    /// message does not fit in them.
+    Steganography(String),
+
+    /// A caller asked for something the operation cannot do, such as
+    /// splitting data into empty chunks.
+    InvalidArgument(String),
+
+    /// The chunk may appear only once and the file already has one.
+    DuplicateChunk(String),
+
+    /// The ordering rules for the chunk type leave no place to insert it.
+    NoValidPosition(String),
+
+    /// The chunk type is one of the critical chunks that make up the
+    /// image itself, which cannot be inserted as extra data.
+    CriticalChunk(String),
+
+    /// Reading or writing the underlying stream failed.
+    Io(io::Error),
+
+    /// An error in the chunk at position `index`, which starts at
+    /// byte `offset` of the file.
+    Chunk {
+        index: usize,
+        offset: usize,
+        source: Box<PngError>,
+    },
+}
+
+impl PngError {
+    /// Wraps `self` with the position of the chunk that caused it.
+    pub fn at_chunk(self, index: usize, offset: usize) -> Self {
+        PngError::Chunk {
+            index,
+            offset,
+            source: Box::new(self),
+        }
+    }
+}
+
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PngError::InvalidSignature => write!(f, "invalid PNG signature"),
+            PngError::TruncatedChunk { needed, available } => {
+                write!(f, "truncated chunk: needs {} bytes but only {} are available", needed, available)
+            }
+            PngError::LengthMismatch { declared, actual } => {
+                write!(f, "chunk declares {} bytes of data but has {}", declared, actual)
+            }
+            PngError::CrcMismatch { expected, actual } => {
+                write!(f, "CRC mismatch: expected {:08x}, found {:08x}", expected, actual)
+            }
+            PngError::ChunkTooLarge(length) => {
+                write!(f, "chunk data is {} bytes long, more than the 2^31 - 1 allowed", length)
+            }
+            PngError::InvalidChunkType(bytes) => write!(f, "invalid chunk type {:?}", bytes),
+            PngError::InvalidChunkTypeLength(len) => {
+                write!(f, "chunk types must be 4 bytes long, got {}", len)
+            }
+            PngError::ChunkNotFound(chunk_type) => write!(f, "no {} chunk found", chunk_type),
+            PngError::InvalidIhdr(reason) => write!(f, "invalid IHDR chunk: {}", reason),
+            PngError::InvalidTextChunk(reason) => write!(f, "invalid text chunk: {}", reason),
+            PngError::MalformedChunk { chunk_type, reason } => {
+                write!(f, "malformed {} chunk: {}", chunk_type, reason)
+            }
+            PngError::Encryption(reason) => write!(f, "encryption error: {}", reason),
+            PngError::InvalidKey(reason) => write!(f, "invalid key file {}", reason),
+            PngError::Signature(reason) => write!(f, "signature error: {}", reason),
+            PngError::Segment(reason) => write!(f, "invalid message segments: {}", reason),
+            PngError::InvalidEnvelope(reason) => write!(f, "invalid message envelope: {}", reason),
+            PngError::Zlib(reason) => write!(f, "invalid zlib stream: {}", reason),
+            PngError::InvalidImageData(reason) => write!(f, "invalid image data: {}", reason),
+            PngError::Steganography(reason) => write!(f, "cannot hide the message in the pixels: {}", reason),
+            PngError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            PngError::DuplicateChunk(chunk_type) => write!(f, "the file already has a {} chunk", chunk_type),
+            PngError::NoValidPosition(chunk_type) => {
+                write!(f, "no position satisfies the ordering rules for {}", chunk_type)
+            }
+            PngError::CriticalChunk(chunk_type) => {
+                write!(f, "{} is a critical chunk type and cannot be inserted", chunk_type)
+            }
+            PngError::Io(e) => write!(f, "I/O error: {}", e),
+            PngError::Chunk { index, offset, source } => {
+                write!(f, "chunk {} at byte {}: {}", index, offset, source)
+            }
+        }
+    }
+}
+
+
+impl From<io::Error> for PngError {
+    fn from(e: io::Error) -> Self {
+        PngError::Io(e)
+    }
+}
+
+
+impl error::Error for PngError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PngError::Io(e) => Some(e),
+            PngError::Chunk { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_chunk_error_display() {
+        let error = PngError::CrcMismatch { expected: 0xdead_beef, actual: 1 }.at_chunk(3, 1234);
+        assert_eq!(
+            error.to_string(),
+            "chunk 3 at byte 1234: CRC mismatch: expected deadbeef, found 00000001"
+        );
+    }
+
+    #[test]
+    fn test_chunk_error_source() {
+        let error = PngError::InvalidSignature.at_chunk(0, 8);
+        assert!(matches!(error.source().unwrap().downcast_ref(), Some(PngError::InvalidSignature)));
+    }
+}
@@ -0,0 +1,187 @@
+use std::convert::TryFrom;
+use crate::error::PngError;
+
+/// The transformation applied to a scanline before compression, from the
+/// byte that starts every scanline in the image data.
+///
+/// Each filter predicts a byte from its neighbours: `a` is the byte one
+/// pixel to the left, `b` the byte above and `c` the byte above and to
+/// the left. Bytes outside the image count as zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 5] = [Filter::None, Filter::Sub, Filter::Up, Filter::Average, Filter::Paeth];
+
+    /// Applies the filter to `row`, the scanline without its filter type
+    /// byte. `previous` is the unfiltered scanline above it, or all zeros
+    /// for the first one.
+    pub fn filter(&self, row: &[u8], previous: &[u8], distance: usize) -> Vec<u8> {
+        (0..row.len())
+            .map(|i| {
+                let (a, c) = if i >= distance { (row[i - distance], previous[i - distance]) } else { (0, 0) };
+                row[i].wrapping_sub(self.predict(a, previous[i], c))
+            })
+            .collect()
+    }
+
+    /// Reverses the filter in place. `row` is the scanline without its
+    /// filter type byte, `previous` the already reconstructed scanline
+    /// above it, or all zeros for the first one.
+    pub fn unfilter(&self, row: &mut [u8], previous: &[u8], distance: usize) {
+        for i in 0..row.len() {
+            let (a, c) = if i >= distance { (row[i - distance], previous[i - distance]) } else { (0, 0) };
+            row[i] = row[i].wrapping_add(self.predict(a, previous[i], c));
+        }
+    }
+
+    fn predict(&self, a: u8, b: u8, c: u8) -> u8 {
+        match self {
+            Filter::None => 0,
+            Filter::Sub => a,
+            Filter::Up => b,
+            Filter::Average => ((a as u16 + b as u16) / 2) as u8,
+            Filter::Paeth => paeth_predictor(a, b, c),
+        }
+    }
+}
+
+
+impl TryFrom<u8> for Filter {
+    type Error = PngError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Filter::None),
+            1 => Ok(Filter::Sub),
+            2 => Ok(Filter::Up),
+            3 => Ok(Filter::Average),
+            4 => Ok(Filter::Paeth),
+            _ => Err(PngError::InvalidImageData(format!("unknown filter type {}", value))),
+        }
+    }
+}
+
+
+/// How the encoder picks the filter of each scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// Use the same filter for every scanline.
+    #[cfg(test)]
+    Fixed(Filter),
+
+    /// Try every filter and keep the one whose output has the smallest
+    /// sum of absolute values, read as signed bytes. This is the
+    /// heuristic the specification suggests and usually compresses best.
+    Adaptive,
+}
+
+impl FilterStrategy {
+    /// Filters `row` and returns the scanline as stored, starting with
+    /// its filter type byte.
+    pub fn apply(&self, row: &[u8], previous: &[u8], distance: usize) -> Vec<u8> {
+        let (filter, filtered) = match self {
+            #[cfg(test)]
+            FilterStrategy::Fixed(filter) => (*filter, filter.filter(row, previous, distance)),
+            FilterStrategy::Adaptive => Filter::ALL.iter()
+                .map(|filter| (*filter, filter.filter(row, previous, distance)))
+                .min_by_key(|(_, filtered)| filtered.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum::<u64>())
+                .expect("there is always a filter to try"),
+        };
+
+        let mut scanline = Vec::with_capacity(1 + filtered.len());
+        scanline.push(filter as u8);
+        scanline.extend(filtered);
+        scanline
+    }
+}
+
+
+/// Picks whichever of `a`, `b` and `c` is closest to `a + b - c`,
+/// preferring them in that order on ties.
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+
+    if pa <= pb && pa <= pc {
+        a
+    }
+    else if pb <= pc {
+        b
+    }
+    else {
+        c
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unfiltered(filter: Filter, row: &[u8], previous: &[u8], distance: usize) -> Vec<u8> {
+        let mut row = row.to_vec();
+        filter.unfilter(&mut row, previous, distance);
+        row
+    }
+
+    #[test]
+    fn test_filter_types() {
+        assert_eq!(Filter::try_from(4).unwrap(), Filter::Paeth);
+        assert!(matches!(Filter::try_from(5), Err(PngError::InvalidImageData(_))));
+    }
+
+    #[test]
+    fn test_unfilter() {
+        let previous = [10, 20, 30, 40];
+        let row = [1, 2, 3, 4];
+
+        assert_eq!(unfiltered(Filter::None, &row, &previous, 2), [1, 2, 3, 4]);
+        assert_eq!(unfiltered(Filter::Sub, &row, &previous, 2), [1, 2, 4, 6]);
+        assert_eq!(unfiltered(Filter::Up, &row, &previous, 2), [11, 22, 33, 44]);
+        assert_eq!(unfiltered(Filter::Average, &row, &previous, 2), [6, 12, 21, 30]);
+        assert_eq!(unfiltered(Filter::Paeth, &row, &previous, 2), [11, 22, 33, 44]);
+    }
+
+    #[test]
+    fn test_unfilter_wraps() {
+        assert_eq!(unfiltered(Filter::Up, &[200], &[100], 1), [44]);
+        assert_eq!(unfiltered(Filter::Average, &[255, 255], &[255, 255], 1), [126, 189]);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let previous = [10, 250, 30, 40, 0, 7];
+        let row = [1, 200, 3, 255, 0, 9];
+
+        for filter in Filter::ALL.iter() {
+            let mut filtered = filter.filter(&row, &previous, 2);
+            filter.unfilter(&mut filtered, &previous, 2);
+            assert_eq!(filtered, row, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_strategies() {
+        let previous = [5, 5, 5, 5];
+        let row = [5, 5, 5, 5];
+
+        assert_eq!(FilterStrategy::Fixed(Filter::Sub).apply(&row, &previous, 1), [1, 5, 0, 0, 0]);
+        assert_eq!(FilterStrategy::Adaptive.apply(&row, &previous, 1), [2, 0, 0, 0, 0]);
+        assert_eq!(FilterStrategy::Adaptive.apply(&[1, 2, 3, 4], &[0; 4], 1), [1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_paeth_predictor() {
+        assert_eq!(paeth_predictor(10, 20, 10), 20);
+        assert_eq!(paeth_predictor(20, 10, 10), 20);
+        assert_eq!(paeth_predictor(10, 10, 20), 10);
+        assert_eq!(paeth_predictor(0, 100, 90), 0);
+    }
+}
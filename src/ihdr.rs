@@ -0,0 +1,295 @@
+use std::fmt;
+use std::convert::TryFrom;
+use serde::Serialize;
+use crate::chunk::Chunk;
+#[cfg(test)]
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+
+/// How pixels are represented, from the IHDR color type byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// Bit depths the specification allows for this color type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    /// Number of samples that make up one pixel.
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+}
+
+
+impl TryFrom<u8> for ColorType {
+    type Error = PngError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(PngError::InvalidIhdr(format!("unknown color type {}", value))),
+        }
+    }
+}
+
+
+impl fmt::Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale + alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+
+/// The order in which pixels are stored in the image data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Interlace {
+    None,
+    Adam7,
+}
+
+
+/// The decoded contents of an IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace: Interlace,
+}
+
+impl Ihdr {
+    pub const LENGTH: usize = 13;
+
+    /// Largest width or height the specification allows.
+    pub const MAX_DIMENSION: u32 = (1 << 31) - 1;
+
+    /// Checks the dimensions and bit depth against the specification.
+    pub fn new(width: u32, height: u32, bit_depth: u8, color_type: ColorType, interlace: Interlace) -> Result<Ihdr, PngError> {
+        if width == 0 || height == 0 || width > Ihdr::MAX_DIMENSION || height > Ihdr::MAX_DIMENSION {
+            return Err(PngError::InvalidIhdr(format!("invalid dimensions {}x{}", width, height)));
+        }
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(PngError::InvalidIhdr(format!("bit depth {} is not allowed for {} images", bit_depth, color_type)));
+        }
+
+        Ok(
+            Ihdr {
+                width,
+                height,
+                bit_depth,
+                color_type,
+                compression_method: 0,
+                filter_method: 0,
+                interlace,
+            }
+        )
+    }
+
+    #[cfg(test)]
+    pub fn to_chunk(self) -> Chunk {
+        let mut data = Vec::with_capacity(Ihdr::LENGTH);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type.to_byte());
+        data.push(self.compression_method);
+        data.push(self.filter_method);
+        data.push(match self.interlace {
+            Interlace::None => 0,
+            Interlace::Adam7 => 1,
+        });
+
+        Chunk::new(ChunkType { bytes: *b"IHDR" }, data)
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// Distance in bytes between a byte and the one it is filtered
+    /// against in the same scanline, at least 1 for bit depths below 8.
+    pub fn filter_distance(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// Length in bytes of a scanline `width` pixels wide, without its
+    /// filter type byte. Pixels narrower than a byte are packed, so the
+    /// last byte may be padded.
+    pub fn row_length(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+}
+
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if chunk.chunk_type().bytes() != b"IHDR" {
+            return Err(PngError::InvalidIhdr(format!("expected an IHDR chunk, got {}", chunk.chunk_type())));
+        }
+
+        let data = chunk.data();
+        if data.len() != Ihdr::LENGTH {
+            return Err(PngError::InvalidIhdr(format!("expected 13 bytes of data, got {}", data.len())));
+        }
+
+        let mut width = [0u8; 4];
+        width.copy_from_slice(&data[0..4]);
+        let width = u32::from_be_bytes(width);
+
+        let mut height = [0u8; 4];
+        height.copy_from_slice(&data[4..8]);
+        let height = u32::from_be_bytes(height);
+
+        let color_type = ColorType::try_from(data[9])?;
+        let (compression_method, filter_method) = (data[10], data[11]);
+        if compression_method != 0 {
+            return Err(PngError::InvalidIhdr(format!("unknown compression method {}", compression_method)));
+        }
+        if filter_method != 0 {
+            return Err(PngError::InvalidIhdr(format!("unknown filter method {}", filter_method)));
+        }
+
+        let interlace = match data[12] {
+            0 => Interlace::None,
+            1 => Interlace::Adam7,
+            other => return Err(PngError::InvalidIhdr(format!("unknown interlace method {}", other))),
+        };
+
+        Ihdr::new(width, height, data[8], color_type, interlace)
+    }
+}
+
+
+impl fmt::Display for Ihdr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}, {}-bit {}", self.width, self.height, self.bit_depth, self.color_type)?;
+        if self.interlace == Interlace::Adam7 {
+            write!(f, ", interlaced")?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn ihdr_chunk(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Chunk {
+        let mut data = vec![];
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+
+    #[test]
+    fn test_valid_ihdr() {
+        let ihdr = Ihdr::try_from(&ihdr_chunk(50, 40, 8, 6, 1)).unwrap();
+
+        assert_eq!(ihdr.width, 50);
+        assert_eq!(ihdr.height, 40);
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+        assert_eq!(ihdr.interlace, Interlace::Adam7);
+        assert_eq!(ihdr.to_string(), "50x40, 8-bit RGBA, interlaced");
+    }
+
+    #[test]
+    fn test_to_chunk() {
+        let chunk = ihdr_chunk(50, 40, 4, 3, 1);
+        let ihdr = Ihdr::try_from(&chunk).unwrap();
+        assert_eq!(ihdr.to_chunk().as_bytes(), chunk.as_bytes());
+
+        assert_eq!(Ihdr::new(3, 2, 16, ColorType::Rgb, Interlace::None).unwrap().to_chunk().data()[8..], [16, 2, 0, 0, 0]);
+        assert!(Ihdr::new(3, 2, 4, ColorType::Rgb, Interlace::None).is_err());
+    }
+
+    #[test]
+    fn test_row_length() {
+        let rgba16 = Ihdr::try_from(&ihdr_chunk(3, 1, 16, 6, 0)).unwrap();
+        assert_eq!(rgba16.bits_per_pixel(), 64);
+        assert_eq!(rgba16.filter_distance(), 8);
+        assert_eq!(rgba16.row_length(3), 24);
+
+        let gray1 = Ihdr::try_from(&ihdr_chunk(9, 1, 1, 0, 0)).unwrap();
+        assert_eq!(gray1.filter_distance(), 1);
+        assert_eq!(gray1.row_length(9), 2);
+        assert_eq!(gray1.row_length(8), 1);
+    }
+
+    #[test]
+    fn test_zero_dimensions() {
+        assert!(matches!(Ihdr::try_from(&ihdr_chunk(0, 40, 8, 6, 0)), Err(PngError::InvalidIhdr(_))));
+        assert!(matches!(Ihdr::try_from(&ihdr_chunk(40, 0, 8, 6, 0)), Err(PngError::InvalidIhdr(_))));
+    }
+
+    #[test]
+    fn test_illegal_bit_depth() {
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 16, 3, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 4, 2, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 3, 0, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 1, 0, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_methods() {
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 8, 5, 0)).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(1, 1, 8, 2, 2)).is_err());
+
+        let mut chunk = ihdr_chunk(1, 1, 8, 2, 0).data().to_vec();
+        chunk[10] = 1;
+        let chunk = Chunk::new(ChunkType::from_str("IHDR").unwrap(), chunk);
+        assert!(Ihdr::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_wrong_length() {
+        let chunk = Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 12]);
+        assert!(matches!(Ihdr::try_from(&chunk), Err(PngError::InvalidIhdr(_))));
+    }
+}
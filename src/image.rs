@@ -0,0 +1,437 @@
+use std::convert::TryFrom;
+use crate::adam7;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::filter::{Filter, FilterStrategy};
+use crate::ihdr::{Ihdr, Interlace};
+#[cfg(test)]
+use crate::ihdr::ColorType;
+use crate::png::Png;
+use crate::zlib;
+
+/// The pixels of an image, decoded from its IDAT chunks.
+///
+/// `data` holds the scanlines top to bottom without their filter type
+/// bytes, each `row_length` bytes long. Samples keep the layout of the
+/// file: those narrower than a byte are packed from the most significant
+/// bit and 16-bit samples are big-endian. Interlaced images are stored
+/// in the same layout; the interlace method in the header only decides
+/// how the pixels are written back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    header: Ihdr,
+    data: Vec<u8>,
+}
+
+impl Image {
+    /// Size of the IDAT chunks written when the caller has no preference.
+    pub const DEFAULT_IDAT_SIZE: usize = 8192;
+
+    /// Wraps raw scanlines laid out as `Image::data` describes.
+    #[cfg(test)]
+    pub fn new(header: Ihdr, data: Vec<u8>) -> Result<Image, PngError> {
+        let expected = header.row_length(header.width) * header.height as usize;
+        if data.len() != expected {
+            return Err(PngError::InvalidImageData(format!("a {} image needs {} bytes of pixels, got {}", header, expected, data.len())));
+        }
+
+        Ok(Image { header, data })
+    }
+
+    pub fn header(&self) -> &Ihdr {
+        &self.header
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Length in bytes of one scanline of `data`.
+    #[cfg(test)]
+    pub fn row_length(&self) -> usize {
+        self.header.row_length(self.header.width)
+    }
+
+    /// The scanline at `y`, counting from the top.
+    #[cfg(test)]
+    pub fn row(&self, y: u32) -> &[u8] {
+        let length = self.row_length();
+        &self.data[y as usize * length..(y as usize + 1) * length]
+    }
+
+    /// Filters every scanline with `strategy`, interlacing them first if
+    /// the header asks for it, deflates the result and
+    /// splits it into IDAT chunks of at most `idat_size` bytes.
+    pub fn to_idat_chunks(&self, strategy: FilterStrategy, idat_size: usize) -> Result<Vec<Chunk>, PngError> {
+        if idat_size == 0 {
+            return Err(PngError::InvalidArgument(String::from("IDAT chunks must hold at least one byte")));
+        }
+        if idat_size > Chunk::MAX_LENGTH {
+            return Err(PngError::ChunkTooLarge(idat_size));
+        }
+
+        let filtered = match self.header.interlace {
+            Interlace::None => filter_scanlines(&self.header, &self.data, self.header.width, strategy),
+            Interlace::Adam7 => adam7::PASSES.iter()
+                .filter(|pass| !pass.is_empty(self.header.width, self.header.height))
+                .flat_map(|pass| {
+                    let (width, _) = pass.size(self.header.width, self.header.height);
+                    filter_scanlines(&self.header, &pass.gather(&self.header, &self.data), width, strategy)
+                })
+                .collect(),
+        };
+
+        Ok(
+            zlib::compress(&filtered)
+                .chunks(idat_size)
+                .map(|data| Chunk::new(ChunkType { bytes: *b"IDAT" }, data.to_vec()))
+                .collect()
+        )
+    }
+
+    /// Builds a minimal PNG holding the image: IHDR, the IDAT chunks
+    /// from `to_idat_chunks` and IEND. Indexed images are rejected since
+    /// they would also need a PLTE chunk.
+    #[cfg(test)]
+    pub fn to_png(&self, strategy: FilterStrategy, idat_size: usize) -> Result<Png, PngError> {
+        if self.header.color_type == ColorType::Indexed {
+            return Err(PngError::InvalidArgument(String::from("indexed images need a PLTE chunk, which to_png cannot write")));
+        }
+
+        let mut chunks = vec![self.header.to_chunk()];
+        chunks.extend(self.to_idat_chunks(strategy, idat_size)?);
+        chunks.push(Chunk::new(ChunkType { bytes: *b"IEND" }, vec![]));
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+
+impl TryFrom<&Png> for Image {
+    type Error = PngError;
+
+    /// Concatenates the IDAT chunks in file order, inflates them,
+    /// reverses the filter of every scanline and undoes any interlacing.
+    /// Inflating stops at the size the header calls for, so a small
+    /// stream cannot expand into more memory than the image needs.
+    fn try_from(png: &Png) -> Result<Self, Self::Error> {
+        let header = png.header_info()?;
+        let compressed: Vec<u8> = png.chunks().iter()
+            .filter(|c| c.chunk_type().bytes() == b"IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+        if compressed.is_empty() {
+            return Err(PngError::ChunkNotFound(String::from("IDAT")));
+        }
+
+        let expected = filtered_length(&header)?;
+        let filtered = zlib::decompress(&compressed, expected)?;
+        let data = match header.interlace {
+            Interlace::None => unfilter_scanlines(&header, &filtered, header.width, header.height)?,
+            Interlace::Adam7 => deinterlace(&header, &filtered)?,
+        };
+
+        Ok(Image { header, data })
+    }
+}
+
+
+/// Filters scanlines `width` pixels wide from `data` with `strategy`,
+/// prefixing each with its filter type.
+fn filter_scanlines(header: &Ihdr, data: &[u8], width: u32, strategy: FilterStrategy) -> Vec<u8> {
+    let row_length = header.row_length(width);
+    let distance = header.filter_distance();
+    let mut filtered = Vec::with_capacity(data.len() + data.len() / row_length);
+    let mut previous = vec![0u8; row_length];
+
+    for row in data.chunks(row_length) {
+        filtered.extend(strategy.apply(row, &previous, distance));
+        previous.copy_from_slice(row);
+    }
+    filtered
+}
+
+/// Size of the filtered scanlines the header calls for, filter type
+/// bytes included, summed over the passes of interlaced images.
+fn filtered_length(header: &Ihdr) -> Result<usize, PngError> {
+    let too_large = || PngError::InvalidImageData(format!("a {}x{} image does not fit in memory", header.width, header.height));
+    let scanlines = |(width, height): (u32, u32)| (header.row_length(width) + 1).checked_mul(height as usize);
+
+    match header.interlace {
+        Interlace::None => scanlines((header.width, header.height)).ok_or_else(too_large),
+        Interlace::Adam7 => adam7::PASSES.iter()
+            .filter(|pass| !pass.is_empty(header.width, header.height))
+            .try_fold(0usize, |total, pass| total.checked_add(scanlines(pass.size(header.width, header.height))?))
+            .ok_or_else(too_large),
+    }
+}
+
+/// Reconstructs the full image from the scanlines of the seven Adam7
+/// passes, each filtered on its own as a reduced image.
+fn deinterlace(header: &Ihdr, filtered: &[u8]) -> Result<Vec<u8>, PngError> {
+    let passes: Vec<(&adam7::Pass, (u32, u32))> = adam7::PASSES.iter()
+        .filter(|pass| !pass.is_empty(header.width, header.height))
+        .map(|pass| (pass, pass.size(header.width, header.height)))
+        .collect();
+
+    let expected = filtered_length(header)?;
+    if filtered.len() != expected {
+        return Err(PngError::InvalidImageData(format!("expected {} bytes of interlaced scanlines, found {}", expected, filtered.len())));
+    }
+
+    let mut data = vec![0u8; header.row_length(header.width) * header.height as usize];
+    let mut offset = 0;
+    for (pass, (width, height)) in passes {
+        let length = (header.row_length(width) + 1) * height as usize;
+        let pixels = unfilter_scanlines(header, &filtered[offset..offset + length], width, height)?;
+        pass.scatter(header, &pixels, &mut data);
+        offset += length;
+    }
+
+    Ok(data)
+}
+
+/// Reconstructs `height` scanlines `width` pixels wide from `filtered`,
+/// which must hold exactly that many, each starting with its filter type.
+fn unfilter_scanlines(header: &Ihdr, filtered: &[u8], width: u32, height: u32) -> Result<Vec<u8>, PngError> {
+    let row_length = header.row_length(width);
+    let expected = (row_length + 1).checked_mul(height as usize)
+        .ok_or_else(|| PngError::InvalidImageData(format!("a {}x{} image does not fit in memory", width, height)))?;
+    if filtered.len() != expected {
+        return Err(PngError::InvalidImageData(format!("expected {} bytes of scanlines, found {}", expected, filtered.len())));
+    }
+
+    let distance = header.filter_distance();
+    let mut data = vec![0u8; row_length * height as usize];
+    let mut previous = vec![0u8; row_length];
+
+    for (y, scanline) in filtered.chunks(row_length + 1).enumerate() {
+        let filter = Filter::try_from(scanline[0])
+            .map_err(|_| PngError::InvalidImageData(format!("scanline {} has unknown filter type {}", y, scanline[0])))?;
+        let row = &mut data[y * row_length..(y + 1) * row_length];
+        row.copy_from_slice(&scanline[1..]);
+        filter.unfilter(row, &previous, distance);
+        previous.copy_from_slice(row);
+    }
+
+    Ok(data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn ihdr_chunk(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Chunk {
+        let mut data = vec![];
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data)
+    }
+
+    /// Builds an image whose compressed scanlines are split over two IDAT chunks.
+    fn testing_png(ihdr: Chunk, scanlines: &[u8]) -> Png {
+        let compressed = zlib::compress(scanlines);
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        Png::from_chunks(vec![
+            ihdr,
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), first.to_vec()),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), second.to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ])
+    }
+
+    #[test]
+    fn test_rgb8_with_every_filter() {
+        let scanlines = [
+            0, 1, 2, 3, 4, 5, 6,
+            1, 1, 2, 3, 4, 5, 6,
+            2, 1, 1, 1, 1, 1, 1,
+            3, 0, 0, 0, 0, 0, 0,
+            4, 0, 0, 0, 0, 0, 0,
+        ];
+        let image = Image::try_from(&testing_png(ihdr_chunk(2, 5, 8, 2, 0), &scanlines)).unwrap();
+
+        assert_eq!(image.row_length(), 6);
+        assert_eq!(image.row(0), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(image.row(1), [1, 2, 3, 5, 7, 9]);
+        assert_eq!(image.row(2), [2, 3, 4, 6, 8, 10]);
+        assert_eq!(image.row(3), [1, 1, 2, 3, 4, 6]);
+        assert_eq!(image.row(4), [1, 1, 2, 3, 4, 6]);
+    }
+
+    #[test]
+    fn test_packed_grayscale() {
+        let scanlines = [
+            0, 0b1010_1010, 0b1000_0000,
+            1, 0b0000_0001, 0b0000_0000,
+        ];
+        let image = Image::try_from(&testing_png(ihdr_chunk(9, 2, 1, 0, 0), &scanlines)).unwrap();
+
+        assert_eq!(image.data(), [0b1010_1010, 0b1000_0000, 0b0000_0001, 0b0000_0001]);
+    }
+
+    #[test]
+    fn test_sixteen_bit_rgba() {
+        let scanlines = [
+            0, 0, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0, 7, 0, 8,
+            1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+        ];
+        let image = Image::try_from(&testing_png(ihdr_chunk(2, 2, 16, 6, 0), &scanlines)).unwrap();
+
+        assert_eq!(image.header().filter_distance(), 8);
+        assert_eq!(image.row(1), [0, 1, 0, 1, 0, 1, 0, 1, 0, 2, 0, 2, 0, 2, 0, 2]);
+    }
+
+    #[test]
+    fn test_wrong_amount_of_data() {
+        let short = testing_png(ihdr_chunk(2, 2, 8, 0, 0), &[0, 1, 2, 0, 3]);
+        assert!(matches!(Image::try_from(&short), Err(PngError::InvalidImageData(_))));
+
+        let long = testing_png(ihdr_chunk(2, 1, 8, 0, 0), &[0, 1, 2, 0]);
+        assert!(matches!(Image::try_from(&long), Err(PngError::Zlib(_))));
+    }
+
+    #[test]
+    fn test_inflate_stops_at_expected_size() {
+        let bomb = testing_png(ihdr_chunk(1, 1, 8, 0, 0), &vec![0; 1 << 24]);
+        assert!(matches!(Image::try_from(&bomb), Err(PngError::Zlib(e)) if e.contains("more than 2 bytes")));
+
+        let corrupt = Png::from_chunks(vec![
+            ihdr_chunk(1, 1, 8, 0, 0),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), b"not zlib".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ]);
+        assert!(matches!(Image::try_from(&corrupt), Err(PngError::Zlib(_))));
+    }
+
+    #[test]
+    fn test_unknown_filter_type() {
+        let png = testing_png(ihdr_chunk(1, 2, 8, 0, 0), &[0, 1, 7, 1]);
+        let error = Image::try_from(&png).unwrap_err();
+        assert_eq!(error.to_string(), "invalid image data: scanline 1 has unknown filter type 7");
+    }
+
+    #[test]
+    fn test_missing_idat() {
+        let png = Png::from_chunks(vec![ihdr_chunk(1, 1, 8, 0, 0)]);
+        assert!(matches!(Image::try_from(&png), Err(PngError::ChunkNotFound(_))));
+    }
+
+    /// An image of arbitrary pixels, with the padding bits at the end of
+    /// packed scanlines left at zero as decoders produce them.
+    fn gradient(header: Ihdr) -> Image {
+        let row_length = header.row_length(header.width);
+        let mut data: Vec<u8> = (0..row_length * header.height as usize).map(|i| (i * 7 % 256) as u8).collect();
+        let padding = row_length * 8 - header.width as usize * header.bits_per_pixel();
+        for row in data.chunks_mut(row_length) {
+            row[row_length - 1] &= !((1u16 << padding) - 1) as u8;
+        }
+        Image::new(header, data).unwrap()
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let headers = [
+            Ihdr::new(5, 4, 8, ColorType::Rgb, Interlace::None).unwrap(),
+            Ihdr::new(13, 3, 1, ColorType::Grayscale, Interlace::None).unwrap(),
+            Ihdr::new(3, 3, 16, ColorType::Rgba, Interlace::None).unwrap(),
+            Ihdr::new(6, 5, 2, ColorType::Grayscale, Interlace::None).unwrap(),
+        ];
+        let strategies = Filter::ALL.iter()
+            .map(|f| FilterStrategy::Fixed(*f))
+            .chain(std::iter::once(FilterStrategy::Adaptive));
+
+        for strategy in strategies {
+            for header in headers.iter() {
+                let image = gradient(*header);
+                let png = image.to_png(strategy, Image::DEFAULT_IDAT_SIZE).unwrap();
+
+                assert!(png.validate().is_ok(), "{:?} {}", strategy, header);
+                let bytes = png.as_bytes();
+                let decoded = Image::try_from(&Png::try_from(&bytes[..]).unwrap()).unwrap();
+                assert_eq!(decoded, image, "{:?} {}", strategy, header);
+            }
+        }
+    }
+
+    #[test]
+    fn test_idat_size() {
+        let image = gradient(Ihdr::new(20, 20, 8, ColorType::Rgba, Interlace::None).unwrap());
+        let png = image.to_png(FilterStrategy::Adaptive, 100).unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+
+        assert!(types.len() > 3);
+        assert_eq!(types.first().unwrap(), "IHDR");
+        assert_eq!(types.last().unwrap(), "IEND");
+        assert!(png.chunks().iter().filter(|c| c.chunk_type().to_string() == "IDAT").all(|c| c.length() <= 100));
+        assert!(png.validate().is_ok());
+        assert_eq!(Image::try_from(&png).unwrap(), image);
+
+        assert!(matches!(image.to_png(FilterStrategy::Adaptive, 0), Err(PngError::InvalidArgument(_))));
+        assert!(matches!(image.to_png(FilterStrategy::Adaptive, Chunk::MAX_LENGTH + 1), Err(PngError::ChunkTooLarge(_))));
+    }
+
+    #[test]
+    fn test_indexed_images() {
+        let image = gradient(Ihdr::new(7, 2, 4, ColorType::Indexed, Interlace::None).unwrap());
+        assert!(matches!(image.to_png(FilterStrategy::Adaptive, Image::DEFAULT_IDAT_SIZE), Err(PngError::InvalidArgument(_))));
+
+        let mut chunks = vec![image.header().to_chunk(), Chunk::new(ChunkType::from_str("PLTE").unwrap(), vec![0; 16 * 3])];
+        chunks.extend(image.to_idat_chunks(FilterStrategy::Adaptive, Image::DEFAULT_IDAT_SIZE).unwrap());
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]));
+        let png = Png::from_chunks(chunks);
+
+        assert!(png.validate().is_ok());
+        assert_eq!(Image::try_from(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        let scanlines = [
+            0, 10,
+            0, 20,
+            1, 30, 10,
+        ];
+        let image = Image::try_from(&testing_png(ihdr_chunk(2, 2, 8, 0, 1), &scanlines)).unwrap();
+
+        assert_eq!(image.data(), [10, 20, 30, 40]);
+        assert_eq!(image.header().interlace, Interlace::Adam7);
+
+        let truncated = testing_png(ihdr_chunk(2, 2, 8, 0, 1), &scanlines[..6]);
+        assert!(matches!(Image::try_from(&truncated), Err(PngError::InvalidImageData(_))));
+    }
+
+    #[test]
+    fn test_interlaced_round_trip() {
+        let sizes = [(1, 1), (3, 2), (13, 9), (32, 17)];
+        let formats = [(1, ColorType::Grayscale), (2, ColorType::Grayscale), (8, ColorType::Rgba), (16, ColorType::GrayscaleAlpha)];
+
+        for &(width, height) in sizes.iter() {
+            for &(bit_depth, color_type) in formats.iter() {
+                let plain = gradient(Ihdr::new(width, height, bit_depth, color_type, Interlace::None).unwrap());
+                let header = Ihdr::new(width, height, bit_depth, color_type, Interlace::Adam7).unwrap();
+                let interlaced = Image::new(header, plain.data().to_vec()).unwrap();
+
+                let png = interlaced.to_png(FilterStrategy::Adaptive, Image::DEFAULT_IDAT_SIZE).unwrap();
+                assert!(png.validate().is_ok(), "{}", header);
+                let decoded = Image::try_from(&png).unwrap();
+                assert_eq!(decoded, interlaced, "{}", header);
+                assert_eq!(decoded.data(), plain.data());
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_checks_length() {
+        let header = Ihdr::new(9, 2, 1, ColorType::Grayscale, Interlace::None).unwrap();
+        assert!(Image::new(header, vec![0; 4]).is_ok());
+        assert!(matches!(Image::new(header, vec![0; 3]), Err(PngError::InvalidImageData(_))));
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use x25519_dalek::{PublicKey, StaticSecret};
+use crate::error::PngError;
+
+/// First line of each kind of key file. Loaders check it, so a key of
+/// the wrong kind or role is rejected instead of silently misused.
+const PRIVATE_KEY_HEADER: &str = "# png-msg X25519 private key";
+const PUBLIC_KEY_HEADER: &str = "# png-msg X25519 public key";
+const SIGNING_KEY_HEADER: &str = "# png-msg Ed25519 signing key";
+const VERIFYING_KEY_HEADER: &str = "# png-msg Ed25519 verifying key";
+
+/// Generates an encryption key pair, writing the private key to `path`
+/// and the public key to `path` with a `.pub` suffix. Returns the public key.
+pub fn generate_key_pair(path: &Path) -> Result<PublicKey, PngError> {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+
+    write_key_pair(path, (PRIVATE_KEY_HEADER, &secret.to_bytes()), (PUBLIC_KEY_HEADER, public.as_bytes()))?;
+    Ok(public)
+}
+
+/// Generates a signing key pair, laid out like `generate_key_pair`.
+/// Returns the verifying key.
+pub fn generate_signing_key_pair(path: &Path) -> Result<VerifyingKey, PngError> {
+    let signing_key = SigningKey::generate(&mut OsRng);
+    let verifying_key = signing_key.verifying_key();
+
+    write_key_pair(path, (SIGNING_KEY_HEADER, &signing_key.to_bytes()), (VERIFYING_KEY_HEADER, verifying_key.as_bytes()))?;
+    Ok(verifying_key)
+}
+
+/// Where `generate_key_pair` writes the public key for the private key at `path`.
+pub fn public_key_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".pub");
+    path.with_file_name(file_name)
+}
+
+/// Loads a public key written by `generate_key_pair`.
+pub fn load_public_key(path: &Path) -> Result<PublicKey, PngError> {
+    Ok(PublicKey::from(read_key(path, PUBLIC_KEY_HEADER)?))
+}
+
+/// Loads a private key written by `generate_key_pair`.
+pub fn load_private_key(path: &Path) -> Result<StaticSecret, PngError> {
+    Ok(StaticSecret::from(read_key(path, PRIVATE_KEY_HEADER)?))
+}
+
+/// Loads a signing key written by `generate_signing_key_pair`.
+pub fn load_signing_key(path: &Path) -> Result<SigningKey, PngError> {
+    Ok(SigningKey::from_bytes(&read_key(path, SIGNING_KEY_HEADER)?))
+}
+
+/// Loads a verifying key written by `generate_signing_key_pair`.
+pub fn load_verifying_key(path: &Path) -> Result<VerifyingKey, PngError> {
+    VerifyingKey::from_bytes(&read_key(path, VERIFYING_KEY_HEADER)?)
+        .map_err(|_| PngError::InvalidKey(format!("{}: not a valid Ed25519 public key", path.display())))
+}
+
+/// Writes the private and public halves of a key pair, each as a header
+/// line followed by the hex encoded key.
+fn write_key_pair(path: &Path, (private_header, secret): (&str, &[u8]), (public_header, public): (&str, &[u8])) -> Result<(), PngError> {
+    write_private(path, &format!("{}\n{}\n", private_header, hex::encode(secret)))?;
+    fs::write(public_key_path(path), format!("{}\n{}\n", public_header, hex::encode(public)))?;
+    Ok(())
+}
+
+/// Reads the 32 hex encoded bytes of a key file whose first line must be
+/// `header`, skipping blank lines and later comments starting with `#`.
+fn read_key(path: &Path, header: &str) -> Result<[u8; 32], PngError> {
+    let contents = fs::read_to_string(path)?;
+    let invalid = |reason: &str| PngError::InvalidKey(format!("{}: {}", path.display(), reason));
+
+    let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next() != Some(header) {
+        return Err(invalid(&format!("wrong key type, expected {}", header.trim_start_matches("# png-msg "))));
+    }
+
+    let mut lines = lines.filter(|line| !line.starts_with('#'));
+    let line = lines.next().ok_or_else(|| invalid("no key found"))?;
+    if lines.next().is_some() {
+        return Err(invalid("expected a single key"));
+    }
+
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(line, &mut key).map_err(|_| invalid("expected 64 hexadecimal digits"))?;
+    Ok(key)
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> Result<(), PngError> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> Result<(), PngError> {
+    use std::io::Write;
+
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("png-msg-{}-{}.key", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_generate_and_load() {
+        let path = testing_path("keygen");
+        let public = generate_key_pair(&path).unwrap();
+
+        let secret = load_private_key(&path).unwrap();
+        let loaded_public = load_public_key(&public_key_path(&path)).unwrap();
+        let overwrite = generate_key_pair(&path);
+        fs::remove_file(public_key_path(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded_public, public);
+        assert_eq!(PublicKey::from(&secret), public);
+        assert!(overwrite.is_err());
+    }
+
+    #[test]
+    fn test_generate_and_load_signing_key() {
+        let path = testing_path("keygen-signing");
+        let verifying_key = generate_signing_key_pair(&path).unwrap();
+
+        let signing_key = load_signing_key(&path).unwrap();
+        let loaded = load_verifying_key(&public_key_path(&path)).unwrap();
+        fs::remove_file(public_key_path(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, verifying_key);
+        assert_eq!(signing_key.verifying_key(), verifying_key);
+    }
+
+    #[test]
+    fn test_wrong_kind_of_key() {
+        let path = testing_path("wrong-kind");
+        let signing_path = testing_path("wrong-kind-signing");
+        generate_key_pair(&path).unwrap();
+        generate_signing_key_pair(&signing_path).unwrap();
+        let (public_path, verifying_path) = (public_key_path(&path), public_key_path(&signing_path));
+
+        let results = [
+            load_private_key(&public_path).map(|_| ()),
+            load_public_key(&path).map(|_| ()),
+            load_private_key(&signing_path).map(|_| ()),
+            load_public_key(&verifying_path).map(|_| ()),
+            load_signing_key(&path).map(|_| ()),
+            load_signing_key(&verifying_path).map(|_| ()),
+            load_verifying_key(&public_path).map(|_| ()),
+            load_verifying_key(&signing_path).map(|_| ()),
+        ];
+        for file in [&path, &public_path, &signing_path, &verifying_path].iter() {
+            fs::remove_file(file).unwrap();
+        }
+
+        for result in results.iter() {
+            assert!(matches!(result, Err(PngError::InvalidKey(e)) if e.contains("wrong key type")), "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_invalid_key_file() {
+        let path = testing_path("invalid");
+        fs::write(&path, "# png-msg X25519 public key\n# comment\nnot hex\n").unwrap();
+        let result = load_public_key(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(PngError::InvalidKey(_))));
+    }
+}
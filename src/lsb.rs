@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use sha2::{Digest, Sha256};
+use crate::crypto;
+use crate::error::PngError;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::image::Image;
+
+/// A channel of the pixels whose samples can carry a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Gray,
+    Alpha,
+}
+
+impl Channel {
+    /// Position of the channel's sample within a pixel of `color_type`,
+    /// if images of that type have the channel.
+    fn index(&self, color_type: ColorType) -> Option<usize> {
+        match (color_type, self) {
+            (ColorType::Grayscale, Channel::Gray) => Some(0),
+            (ColorType::GrayscaleAlpha, Channel::Gray) => Some(0),
+            (ColorType::GrayscaleAlpha, Channel::Alpha) => Some(1),
+            (ColorType::Rgb, Channel::Red) | (ColorType::Rgba, Channel::Red) => Some(0),
+            (ColorType::Rgb, Channel::Green) | (ColorType::Rgba, Channel::Green) => Some(1),
+            (ColorType::Rgb, Channel::Blue) | (ColorType::Rgba, Channel::Blue) => Some(2),
+            (ColorType::Rgba, Channel::Alpha) => Some(3),
+            _ => None,
+        }
+    }
+}
+
+
+/// Hides bytes in the least significant bits of pixel samples.
+///
+/// The bits of the message are spread most significant first over the
+/// low `bits` bits of the chosen samples, visited in an order drawn from
+/// a ChaCha20 generator. Only 8 and 16-bit images without a palette are
+/// supported: changing the low bits of a palette index or of a packed
+/// sample changes the color visibly. For 16-bit samples the low byte is used.
+///
+/// By default the generator is seeded from the image dimensions and a
+/// fixed salt. That keeps the payload out of the first samples a naive
+/// scanner reads, but anyone who knows this tool can still find it. A
+/// scattered embedding seeds the generator from a passphrase instead, so
+/// the payload cannot be located without it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Embedding {
+    bits: u8,
+    samples: Vec<usize>,
+    seed: [u8; 32],
+}
+
+impl Embedding {
+    /// Stands in for the salt when deriving the seed of a scattered order.
+    const ORDER_CONTEXT: &'static [u8] = b"png-msg lsb order v1";
+
+    /// Hashed with the image dimensions into the seed of the default order.
+    const DEFAULT_ORDER_SALT: &'static [u8] = b"png-msg lsb default order v1";
+
+    /// Checks that images described by `header` can carry a message in
+    /// `channels`, or in every channel but alpha if it is empty.
+    pub fn new(header: &Ihdr, bits: u8, channels: &[Channel]) -> Result<Self, PngError> {
+        if header.color_type == ColorType::Indexed || header.bit_depth < 8 {
+            return Err(PngError::Steganography(format!("cannot hide a message in the pixels of a {} image", header)));
+        }
+        if bits == 0 || bits > 8 {
+            return Err(PngError::Steganography(format!("expected 1 to 8 bits per sample, got {}", bits)));
+        }
+
+        let mut samples = if channels.is_empty() {
+            let has_alpha = matches!(header.color_type, ColorType::GrayscaleAlpha | ColorType::Rgba);
+            (0..header.color_type.channels() - has_alpha as usize).collect()
+        }
+        else {
+            channels.iter()
+                .map(|channel| {
+                    channel.index(header.color_type).ok_or_else(|| {
+                        PngError::Steganography(format!("{} images have no {:?} channel", header.color_type, channel))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        samples.sort_unstable();
+        samples.dedup();
+
+        let seed = Sha256::new()
+            .chain_update(Embedding::DEFAULT_ORDER_SALT)
+            .chain_update(header.width.to_be_bytes())
+            .chain_update(header.height.to_be_bytes())
+            .chain_update([header.bit_depth, header.color_type.to_byte()])
+            .finalize()
+            .into();
+
+        Ok(Embedding { bits, samples, seed })
+    }
+
+    /// Visits the samples in an order derived from `passphrase`.
+    pub fn scattered(mut self, passphrase: &str) -> Result<Self, PngError> {
+        self.seed = crypto::derive_seed(passphrase, Embedding::ORDER_CONTEXT)?;
+        Ok(self)
+    }
+
+    /// Number of whole bytes the pixels of `header` can hold.
+    pub fn capacity(&self, header: &Ihdr) -> usize {
+        let samples = header.width as usize * header.height as usize * self.samples.len();
+        samples.saturating_mul(self.bits as usize) / 8
+    }
+
+    /// Overwrites the low bits of the chosen samples of `image` with
+    /// `message`. Samples after the end of the message are left alone.
+    pub fn embed(&self, image: &mut Image, message: &[u8]) -> Result<(), PngError> {
+        let header = *image.header();
+        self.check_capacity(&header, message.len())?;
+
+        let mask = low_bits_mask(self.bits);
+        let mut bits = message.iter().flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1)).peekable();
+        let data = image.data_mut();
+
+        for offset in self.sample_offsets(&header) {
+            if bits.peek().is_none() {
+                break;
+            }
+
+            let value = (0..self.bits).fold(0, |value, _| value << 1 | bits.next().unwrap_or(0));
+            data[offset] = (data[offset] & !mask) | value;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the first `length` bytes hidden by `embed`.
+    pub fn extract(&self, image: &Image, length: usize) -> Result<Vec<u8>, PngError> {
+        let header = image.header();
+        self.check_capacity(header, length)?;
+
+        let mask = low_bits_mask(self.bits);
+        let data = image.data();
+        let mut message = Vec::with_capacity(length);
+        let (mut byte, mut count) = (0u8, 0);
+
+        for offset in self.sample_offsets(header) {
+            if message.len() == length {
+                break;
+            }
+
+            let value = data[offset] & mask;
+            for i in (0..self.bits).rev() {
+                byte = byte << 1 | (value >> i) & 1;
+                count += 1;
+                if count == 8 {
+                    message.push(byte);
+                    byte = 0;
+                    count = 0;
+                }
+            }
+        }
+
+        message.truncate(length);
+        Ok(message)
+    }
+
+    /// Offsets in the image data of the bytes holding the low bits of the
+    /// chosen samples, in the order the message visits them.
+    fn sample_offsets<'a>(&'a self, header: &Ihdr) -> impl Iterator<Item = usize> + 'a {
+        let bytes_per_sample = header.bit_depth as usize / 8;
+        let bytes_per_pixel = header.color_type.channels() * bytes_per_sample;
+        let slots = header.width as usize * header.height as usize * self.samples.len();
+
+        Permutation::new(slots, ChaCha20Rng::from_seed(self.seed)).map(move |slot| {
+            let (pixel, sample) = (slot / self.samples.len(), self.samples[slot % self.samples.len()]);
+            pixel * bytes_per_pixel + (sample + 1) * bytes_per_sample - 1
+        })
+    }
+
+    fn check_capacity(&self, header: &Ihdr, length: usize) -> Result<(), PngError> {
+        let capacity = self.capacity(header);
+        if length > capacity {
+            return Err(PngError::Steganography(format!(
+                "{} bytes do not fit, the pixels hold at most {} with {} bit(s) per sample",
+                length, capacity, self.bits
+            )));
+        }
+        Ok(())
+    }
+}
+
+
+fn low_bits_mask(bits: u8) -> u8 {
+    ((1u16 << bits) - 1) as u8
+}
+
+/// A random permutation of `0..len`, produced lazily by a Fisher-Yates
+/// shuffle that only remembers the positions it has swapped, so hiding a
+/// short message in a large image stays cheap.
+struct Permutation {
+    rng: ChaCha20Rng,
+    len: usize,
+    next: usize,
+    swapped: HashMap<usize, usize>,
+}
+
+impl Permutation {
+    fn new(len: usize, rng: ChaCha20Rng) -> Self {
+        Permutation { rng, len, next: 0, swapped: HashMap::new() }
+    }
+
+    /// A uniformly distributed number below `bound`, rejecting the
+    /// outputs that would make some numbers more likely than others.
+    fn below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.rng.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+
+impl Iterator for Permutation {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.next == self.len {
+            return None;
+        }
+
+        let i = self.next;
+        let j = i + self.below((self.len - i) as u64) as usize;
+        let value = self.swapped.get(&j).copied().unwrap_or(j);
+        let displaced = self.swapped.remove(&i).unwrap_or(i);
+        if j != i {
+            self.swapped.insert(j, displaced);
+        }
+
+        self.next += 1;
+        Some(value)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::Interlace;
+
+    fn testing_image(bit_depth: u8, color_type: ColorType) -> Image {
+        let header = Ihdr::new(4, 3, bit_depth, color_type, Interlace::None).unwrap();
+        let length = header.row_length(4) * 3;
+        Image::new(header, (0..length).map(|i| (i * 13 % 256) as u8).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let mut image = testing_image(8, ColorType::Rgba);
+        let original = image.clone();
+        let embedding = Embedding::new(image.header(), 1, &[]).unwrap();
+
+        assert_eq!(embedding.capacity(image.header()), 4);
+        embedding.embed(&mut image, b"hi").unwrap();
+        assert_eq!(embedding.extract(&image, 2).unwrap(), b"hi");
+
+        for (i, (before, after)) in original.data().iter().zip(image.data()).enumerate() {
+            assert_eq!(before & !1, after & !1);
+            if i % 4 == 3 {
+                assert_eq!(before, after, "byte {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bits_and_channels() {
+        let mut image = testing_image(16, ColorType::Rgb);
+        let original = image.clone();
+        let embedding = Embedding::new(image.header(), 4, &[Channel::Blue, Channel::Red, Channel::Blue]).unwrap();
+
+        assert_eq!(embedding.capacity(image.header()), 12);
+        embedding.embed(&mut image, b"hello world!").unwrap();
+        assert_eq!(embedding.extract(&image, 12).unwrap(), b"hello world!");
+        for (i, (before, after)) in original.data().iter().zip(image.data()).enumerate() {
+            if i % 6 == 1 || i % 6 == 5 {
+                assert_eq!(before & 0xf0, after & 0xf0, "byte {}", i);
+            }
+            else {
+                assert_eq!(before, after, "byte {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity_exceeded() {
+        let mut image = testing_image(8, ColorType::Grayscale);
+        let embedding = Embedding::new(image.header(), 2, &[]).unwrap();
+
+        assert_eq!(embedding.capacity(image.header()), 3);
+        assert!(matches!(embedding.embed(&mut image, b"four"), Err(PngError::Steganography(_))));
+        assert!(embedding.extract(&image, 4).is_err());
+    }
+
+    #[test]
+    fn test_permutation() {
+        let mut values: Vec<usize> = Permutation::new(100, ChaCha20Rng::from_seed([7; 32])).collect();
+        assert_ne!(values, (0..100).collect::<Vec<_>>());
+
+        values.sort_unstable();
+        assert_eq!(values, (0..100).collect::<Vec<_>>());
+        assert_eq!(Permutation::new(0, ChaCha20Rng::from_seed([7; 32])).count(), 0);
+    }
+
+    #[test]
+    fn test_scattered() {
+        let header = Ihdr::new(32, 32, 8, ColorType::Rgb, Interlace::None).unwrap();
+        let mut image = Image::new(header, vec![0; 32 * 32 * 3]).unwrap();
+        let embedding = Embedding::new(&header, 1, &[]).unwrap().scattered("secret").unwrap();
+
+        embedding.embed(&mut image, &[0xff; 4]).unwrap();
+        assert_eq!(embedding.extract(&image, 4).unwrap(), [0xff; 4]);
+
+        let changed: Vec<usize> = (0..image.data().len()).filter(|&i| image.data()[i] != 0).collect();
+        assert_eq!(changed.len(), 32);
+        assert!(changed.last().unwrap() > &64);
+
+        let other = Embedding::new(&header, 1, &[]).unwrap().scattered("guess").unwrap();
+        assert_ne!(other.extract(&image, 4).unwrap(), [0xff; 4]);
+        assert_ne!(Embedding::new(&header, 1, &[]).unwrap().extract(&image, 4).unwrap(), [0xff; 4]);
+    }
+
+    #[test]
+    fn test_default_order_is_spread() {
+        let header = Ihdr::new(64, 64, 8, ColorType::Rgb, Interlace::None).unwrap();
+        let mut image = Image::new(header, vec![0; 64 * 64 * 3]).unwrap();
+        let embedding = Embedding::new(&header, 1, &[]).unwrap();
+        embedding.embed(&mut image, b"PMSG").unwrap();
+
+        let changed: Vec<usize> = (0..image.data().len()).filter(|&i| image.data()[i] != 0).collect();
+        assert_eq!(changed.len(), 14);
+        assert!(changed.iter().filter(|&&i| i < 32).count() < 2);
+        assert!(changed.last().unwrap() > &(64 * 64));
+        assert_eq!(embedding.extract(&image, 4).unwrap(), b"PMSG");
+
+        let resized = Ihdr::new(64, 63, 8, ColorType::Rgb, Interlace::None).unwrap();
+        assert_ne!(Embedding::new(&resized, 1, &[]).unwrap(), embedding);
+    }
+
+    #[test]
+    fn test_unsupported_images_and_channels() {
+        let indexed = Ihdr::new(4, 4, 8, ColorType::Indexed, Interlace::None).unwrap();
+        assert!(Embedding::new(&indexed, 1, &[]).is_err());
+
+        let packed = Ihdr::new(4, 4, 4, ColorType::Grayscale, Interlace::None).unwrap();
+        assert!(Embedding::new(&packed, 1, &[]).is_err());
+
+        let gray = Ihdr::new(4, 4, 8, ColorType::Grayscale, Interlace::None).unwrap();
+        assert!(Embedding::new(&gray, 1, &[Channel::Red]).is_err());
+        assert!(Embedding::new(&gray, 9, &[]).is_err());
+        assert!(Embedding::new(&gray, 1, &[Channel::Gray]).is_ok());
+    }
+}
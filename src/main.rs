@@ -1,12 +1,49 @@
+mod adam7;
+mod ancillary;
 mod args;
 mod chunk;
 mod chunk_type;
 mod commands;
+mod crypto;
+mod editor;
+mod error;
+mod filter;
+mod ihdr;
+mod image;
+mod keys;
+mod lsb;
+mod message;
 mod png;
+mod reader;
+mod segment;
+mod signature;
+mod text;
+mod validation;
+mod writer;
+mod zlib;
+
+use clap::Parser;
+use args::{Cli, Command};
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
-fn main() -> Result<()> {
-    todo!()
-}
\ No newline at end of file
+fn main() {
+    let cli = Cli::parse().resolved().unwrap_or_else(|e| e.exit());
+
+    let result = match cli.command {
+        Command::Encode(args) => commands::encode(args),
+        Command::Decode(args) => commands::decode(args),
+        Command::Remove(args) => commands::remove(args),
+        Command::Print(args) => commands::print(args),
+        Command::Validate(args) => commands::validate(args),
+        Command::Keygen(args) => commands::keygen(args),
+        Command::Sign(args) => commands::sign(args),
+        Command::Verify(args) => commands::verify(args),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,352 @@
+//! The envelope wrapped around every message `encode` stores in a chunk.
+//!
+//! Each chunk holding a message starts with this header, all integers big-endian:
+//!
+//! | Bytes | Field        | Meaning                                            |
+//! |-------|--------------|----------------------------------------------------|
+//! | 4     | magic        | `PMSG`                                             |
+//! | 1     | version      | `1`                                                |
+//! | 1     | flags        | how the payload was transformed, see below         |
+//! | 1     | content type | `0` for UTF-8 text, `1` for binary data            |
+//! | 4     | length       | length of the payload that follows                 |
+//!
+//! The flags are, from the lowest bit: compressed with zlib, encrypted
+//! with a passphrase, encrypted to recipients, and split into segments.
+//! When packing, the message is compressed, then encrypted, then split,
+//! and unpacking undoes the steps in reverse. A split message has one
+//! envelope per chunk, each holding a segment with its own sequence header.
+//!
+//! Readers reject versions and flags they do not know, so new features
+//! must claim a new flag or version instead of changing what existing
+//! ones mean.
+
+use std::convert::TryFrom;
+use x25519_dalek::{PublicKey, StaticSecret};
+use crate::crypto;
+use crate::error::PngError;
+use crate::segment;
+use crate::zlib;
+
+/// What the message body holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Text,
+    Binary,
+}
+
+impl TryFrom<u8> for ContentType {
+    type Error = PngError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ContentType::Text),
+            1 => Ok(ContentType::Binary),
+            _ => Err(PngError::InvalidEnvelope(format!("unknown content type {}", value))),
+        }
+    }
+}
+
+
+/// How a message is encrypted when it is packed.
+#[derive(Clone)]
+pub enum Encryption {
+    None,
+    Passphrase(String),
+    Recipients(Vec<PublicKey>),
+}
+
+/// The key used to decrypt a message when it is unpacked.
+pub enum Decryption<'a> {
+    None,
+    Passphrase(&'a str),
+    Identity(&'a StaticSecret),
+}
+
+
+/// The header and payload stored in one chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    flags: u8,
+    content_type: ContentType,
+    payload: Vec<u8>,
+}
+
+impl Envelope {
+    pub const MAGIC: [u8; 4] = *b"PMSG";
+    pub const VERSION: u8 = 1;
+    pub const HEADER_LENGTH: usize = 11;
+
+    pub const COMPRESSED: u8 = 0b0001;
+    pub const PASSPHRASE: u8 = 0b0010;
+    pub const RECIPIENTS: u8 = 0b0100;
+    pub const SEGMENTED: u8 = 0b1000;
+    const KNOWN_FLAGS: u8 = Envelope::COMPRESSED | Envelope::PASSPHRASE | Envelope::RECIPIENTS | Envelope::SEGMENTED;
+
+    /// Whether `data` starts with the envelope magic. Chunks written before
+    /// the envelope existed hold the raw message instead.
+    pub fn is_envelope(data: &[u8]) -> bool {
+        data.starts_with(&Envelope::MAGIC)
+    }
+
+    /// Length of the whole envelope that `header`, its first
+    /// `HEADER_LENGTH` bytes, starts.
+    pub fn declared_length(header: &[u8]) -> Result<usize, PngError> {
+        if !Envelope::is_envelope(header) || header.len() < Envelope::HEADER_LENGTH {
+            return Err(PngError::InvalidEnvelope(String::from("missing PMSG magic")));
+        }
+        Ok(Envelope::HEADER_LENGTH + u32::from_be_bytes([header[7], header[8], header[9], header[10]]) as usize)
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Envelope::HEADER_LENGTH + self.payload.len());
+        bytes.extend_from_slice(&Envelope::MAGIC);
+        bytes.push(Envelope::VERSION);
+        bytes.push(self.flags);
+        bytes.push(match self.content_type {
+            ContentType::Text => 0,
+            ContentType::Binary => 1,
+        });
+        bytes.extend_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+
+impl TryFrom<&[u8]> for Envelope {
+    type Error = PngError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if !Envelope::is_envelope(data) {
+            return Err(PngError::InvalidEnvelope(String::from("missing PMSG magic")));
+        }
+        if data.len() < Envelope::HEADER_LENGTH {
+            return Err(PngError::InvalidEnvelope(format!("header needs {} bytes, got {}", Envelope::HEADER_LENGTH, data.len())));
+        }
+
+        let version = data[4];
+        if version != Envelope::VERSION {
+            return Err(PngError::InvalidEnvelope(format!("unsupported version {}", version)));
+        }
+
+        let flags = data[5];
+        if flags & !Envelope::KNOWN_FLAGS != 0 {
+            return Err(PngError::InvalidEnvelope(format!("unsupported flags {:#010b}", flags)));
+        }
+        if flags & Envelope::PASSPHRASE != 0 && flags & Envelope::RECIPIENTS != 0 {
+            return Err(PngError::InvalidEnvelope(String::from("a message cannot use both kinds of encryption")));
+        }
+
+        let content_type = ContentType::try_from(data[6])?;
+        let length = u32::from_be_bytes([data[7], data[8], data[9], data[10]]) as usize;
+        let payload = &data[Envelope::HEADER_LENGTH..];
+        if payload.len() != length {
+            return Err(PngError::InvalidEnvelope(format!("header declares {} bytes of payload, found {}", length, payload.len())));
+        }
+
+        Ok(
+            Envelope {
+                flags,
+                content_type,
+                payload: payload.to_vec(),
+            }
+        )
+    }
+}
+
+
+/// Longest message body `pack` accepts, and so the most a compressed
+/// payload may inflate to when it is unpacked.
+pub const MAX_BODY_LENGTH: usize = 1 << 24;
+
+/// Compresses, encrypts and splits `body` as asked, returning the data
+/// of each chunk to store.
+pub fn pack(
+    body: &[u8],
+    content_type: ContentType,
+    compress: bool,
+    encryption: &Encryption,
+    max_chunk_size: Option<usize>,
+) -> Result<Vec<Vec<u8>>, PngError> {
+    if body.len() > MAX_BODY_LENGTH {
+        return Err(PngError::InvalidEnvelope(format!("messages are limited to {} bytes, got {}", MAX_BODY_LENGTH, body.len())));
+    }
+
+    let mut flags = 0;
+    let mut payload = body.to_vec();
+
+    if compress {
+        flags |= Envelope::COMPRESSED;
+        payload = zlib::compress(&payload);
+    }
+
+    match encryption {
+        Encryption::None => {}
+        Encryption::Passphrase(passphrase) => {
+            flags |= Envelope::PASSPHRASE;
+            payload = crypto::encrypt(&payload, passphrase)?;
+        }
+        Encryption::Recipients(recipients) => {
+            flags |= Envelope::RECIPIENTS;
+            payload = crypto::encrypt_to_recipients(&payload, recipients)?;
+        }
+    }
+
+    let payloads = match max_chunk_size {
+        Some(max_size) => {
+            flags |= Envelope::SEGMENTED;
+            if max_size <= Envelope::HEADER_LENGTH {
+                return Err(PngError::InvalidEnvelope(format!("chunks must be larger than the {} byte header", Envelope::HEADER_LENGTH)));
+            }
+            segment::split(&payload, max_size - Envelope::HEADER_LENGTH)?
+        }
+        None => vec![payload],
+    };
+
+    Ok(
+        payloads.into_iter()
+            .map(|payload| Envelope { flags, content_type, payload }.to_bytes())
+            .collect()
+    )
+}
+
+/// Reverses `pack`, given the data of every chunk holding the message in
+/// file order. Extra chunks are only read if the first one is segmented.
+pub fn unpack<'a, I>(chunks: I, decryption: &Decryption) -> Result<(ContentType, Vec<u8>), PngError>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut chunks = chunks.into_iter();
+    let first = match chunks.next() {
+        Some(data) => Envelope::try_from(data)?,
+        None => return Err(PngError::InvalidEnvelope(String::from("no message found"))),
+    };
+    let (flags, content_type) = (first.flags, first.content_type);
+
+    let mut payload = if flags & Envelope::SEGMENTED != 0 {
+        let mut envelopes = vec![first];
+        for data in chunks {
+            let envelope = Envelope::try_from(data)?;
+            if envelope.flags != flags || envelope.content_type != content_type {
+                return Err(PngError::InvalidEnvelope(String::from("segments disagree on flags or content type")));
+            }
+            envelopes.push(envelope);
+        }
+        segment::join(envelopes.iter().map(|e| &e.payload[..]))?
+    }
+    else {
+        first.payload
+    };
+
+    if flags & Envelope::PASSPHRASE != 0 {
+        payload = match decryption {
+            Decryption::Passphrase(passphrase) => crypto::decrypt(&payload, passphrase)?,
+            _ => return Err(PngError::Encryption(String::from("the message is encrypted with a passphrase"))),
+        };
+    }
+    else if flags & Envelope::RECIPIENTS != 0 {
+        payload = match decryption {
+            Decryption::Identity(identity) => crypto::decrypt_with_identity(&payload, identity)?,
+            _ => return Err(PngError::Encryption(String::from("the message is encrypted to recipients, an identity is needed"))),
+        };
+    }
+
+    if flags & Envelope::COMPRESSED != 0 {
+        payload = zlib::decompress(&payload, MAX_BODY_LENGTH)?;
+    }
+
+    Ok((content_type, payload))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chacha20poly1305::aead::OsRng;
+
+    fn unpack_vecs(chunks: &[Vec<u8>], decryption: &Decryption) -> Result<(ContentType, Vec<u8>), PngError> {
+        unpack(chunks.iter().map(Vec::as_slice), decryption)
+    }
+
+    #[test]
+    fn test_plain_envelope_layout() {
+        let chunks = pack(b"hello", ContentType::Text, false, &Encryption::None, None).unwrap();
+
+        assert_eq!(chunks, [b"PMSG\x01\x00\x00\x00\x00\x00\x05hello".to_vec()]);
+        assert_eq!(unpack_vecs(&chunks, &Decryption::None).unwrap(), (ContentType::Text, b"hello".to_vec()));
+    }
+
+    #[test]
+    fn test_every_transformation() {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let encryption = Encryption::Recipients(vec![PublicKey::from(&secret)]);
+        let body = b"hello ".repeat(50);
+        let chunks = pack(&body, ContentType::Binary, true, &encryption, Some(32)).unwrap();
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.len() <= 32));
+        let envelope = Envelope::try_from(&chunks[0][..]).unwrap();
+        assert_eq!(envelope.flags(), Envelope::COMPRESSED | Envelope::RECIPIENTS | Envelope::SEGMENTED);
+        assert_eq!(envelope.content_type, ContentType::Binary);
+
+        assert_eq!(unpack_vecs(&chunks, &Decryption::Identity(&secret)).unwrap(), (ContentType::Binary, body));
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let chunks = pack(b"hello", ContentType::Text, false, &Encryption::Passphrase(String::from("pw")), None).unwrap();
+
+        assert!(matches!(unpack_vecs(&chunks, &Decryption::None), Err(PngError::Encryption(_))));
+        assert_eq!(unpack_vecs(&chunks, &Decryption::Passphrase("pw")).unwrap().1, b"hello");
+    }
+
+    #[test]
+    fn test_rejects_unknown_version_and_flags() {
+        let chunk = pack(b"hello", ContentType::Text, false, &Encryption::None, None).unwrap().remove(0);
+
+        let mut future = chunk.clone();
+        future[4] = 2;
+        assert!(matches!(Envelope::try_from(&future[..]), Err(PngError::InvalidEnvelope(_))));
+
+        let mut unknown_flag = chunk.clone();
+        unknown_flag[5] = 0b1_0000;
+        assert!(Envelope::try_from(&unknown_flag[..]).is_err());
+
+        let mut bad_length = chunk;
+        bad_length[10] = 6;
+        assert!(Envelope::try_from(&bad_length[..]).is_err());
+    }
+
+    #[test]
+    fn test_body_length_limit() {
+        let body = vec![0; MAX_BODY_LENGTH + 1];
+        assert!(pack(&body, ContentType::Binary, true, &Encryption::None, None).is_err());
+
+        let mut envelope = pack(b"", ContentType::Binary, false, &Encryption::None, None).unwrap().remove(0);
+        envelope[5] = Envelope::COMPRESSED;
+        let payload = zlib::compress(&body);
+        envelope.truncate(Envelope::HEADER_LENGTH - 4);
+        envelope.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        envelope.extend(payload);
+        assert!(matches!(unpack_vecs(&[envelope], &Decryption::None), Err(PngError::Zlib(_))));
+    }
+
+    #[test]
+    fn test_declared_length() {
+        let chunk = pack(b"hello", ContentType::Text, false, &Encryption::None, None).unwrap().remove(0);
+
+        assert_eq!(Envelope::declared_length(&chunk[..Envelope::HEADER_LENGTH]).unwrap(), chunk.len());
+        assert!(Envelope::declared_length(&chunk[..Envelope::HEADER_LENGTH - 1]).is_err());
+        assert!(Envelope::declared_length(&[0; Envelope::HEADER_LENGTH]).is_err());
+    }
+
+    #[test]
+    fn test_raw_data_is_not_an_envelope() {
+        assert!(!Envelope::is_envelope(b"hello"));
+        assert!(Envelope::try_from(&b"PMSG"[..]).is_err());
+    }
+}
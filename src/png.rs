@@ -1,29 +1,53 @@
 use crate::chunk::Chunk;
-
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::ihdr::Ihdr;
+use crate::image::Image;
+use crate::reader::PngReader;
+use crate::validation::{check_chunk_order, insertion_index, Position, Violation};
+use crate::writer::PngWriter;
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::fmt;
+use std::io::Write;
 use std::convert::TryFrom;
 
 
-struct Png {
+pub struct Png {
     header: [u8; 8],
     chunks: Vec<Chunk>,
 }
 
 impl Png {
-    const STANDARD_HEADER: [u8; 8] = [ 137, 80, 78, 71, 13, 10, 26, 10 ];
+    pub const STANDARD_HEADER: [u8; 8] = [ 137, 80, 78, 71, 13, 10, 26, 10 ];
+
+    /// Standard unsafe-to-copy chunks that stay valid when only the image
+    /// data changes, since they describe the header or the palette.
+    const KEPT_UNSAFE_TO_COPY: [[u8; 4]; 8] = [*b"cHRM", *b"gAMA", *b"iCCP", *b"sBIT", *b"sRGB", *b"bKGD", *b"hIST", *b"tRNS"];
 
-    fn from_chunks(chunks: Vec<Chunk>) -> Png {
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Png { 
             header: Png::STANDARD_HEADER,
             chunks
         }
     }
 
-    fn append_chunk(&mut self, chunk: Chunk) {
+    #[cfg(test)]
+    pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
 
-    fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, &'static str> {
+    /// Inserts `chunk` at `position`, moved as little as needed to keep
+    /// the ordering rules of the PNG specification.
+    /// Unlike `append_chunk`, this never places a chunk after `IEND`.
+    pub fn insert_chunk(&mut self, chunk: Chunk, position: &Position) -> Result<(), PngError> {
+        let chunk_types: Vec<&ChunkType> = self.chunks.iter().map(Chunk::chunk_type).collect();
+        let index = insertion_index(&chunk_types, chunk.chunk_type(), position)?;
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+
+    pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, PngError> {
         let mut index = None;
         for (i, chunk) in self.chunks.iter().enumerate() {
             if chunk.chunk_type().to_string() == chunk_type {
@@ -35,77 +59,146 @@ impl Png {
             Ok(self.chunks.remove(i))
         }
         else {
-            Err("Chunk not found")
+            Err(PngError::ChunkNotFound(chunk_type.to_string()))
         }
     }
 
-    fn header(&self) -> &[u8; 8] {
-        &self.header
+    /// Checks the chunk sequence against the ordering and multiplicity
+    /// rules of the PNG specification, reporting every violation found.
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let chunk_types: Vec<&ChunkType> = self.chunks.iter().map(Chunk::chunk_type).collect();
+        let violations = check_chunk_order(&chunk_types);
+
+        if violations.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(violations)
+        }
+    }
+
+    /// Decodes the IHDR chunk.
+    pub fn header_info(&self) -> Result<Ihdr, PngError> {
+        match self.chunk_by_type("IHDR") {
+            Some(chunk) => Ihdr::try_from(chunk),
+            None => Err(PngError::ChunkNotFound(String::from("IHDR"))),
+        }
     }
 
-    fn chunks(&self) -> &[Chunk] {
+    /// Decodes the pixels stored in the IDAT chunks.
+    pub fn image(&self) -> Result<Image, PngError> {
+        Image::try_from(self)
+    }
+
+    /// Replaces every IDAT chunk with `idat_chunks`, placed where the
+    /// first one was.
+    ///
+    /// As the specification asks of editors that change critical data,
+    /// ancillary chunks that are unsafe to copy, such as signatures, are
+    /// dropped. The standard ones only describe the header and palette,
+    /// which stay as they were, so those are kept.
+    pub fn replace_image_data(&mut self, idat_chunks: Vec<Chunk>) -> Result<(), PngError> {
+        if !self.chunks.iter().any(|c| c.chunk_type().bytes() == b"IDAT") {
+            return Err(PngError::ChunkNotFound(String::from("IDAT")));
+        }
+
+        self.chunks.retain(|c| {
+            let chunk_type = c.chunk_type();
+            chunk_type.is_critical() || chunk_type.is_safe_to_copy() || Png::KEPT_UNSAFE_TO_COPY.contains(chunk_type.bytes())
+        });
+        let index = self.chunks.iter()
+            .position(|c| c.chunk_type().bytes() == b"IDAT")
+            .expect("IDAT chunks are critical and kept");
+        self.chunks.retain(|c| c.chunk_type().bytes() != b"IDAT");
+        self.chunks.splice(index..index, idat_chunks);
+        Ok(())
+    }
+
+    pub fn chunks(&self) -> &[Chunk] {
         &self.chunks
     }
 
-    fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         self.chunks.iter().find(|chunk| chunk.chunk_type().to_string() == chunk_type)
     }
 
-    fn as_bytes(&self) -> Vec<u8> {
-        let chunk_bytes: Vec<u8> = self.chunks.iter().flat_map(|c| c.as_bytes()).collect();
+    #[cfg(test)]
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let length = self.header.len() + self.chunks.iter().map(|c| c.length() as usize + 12).sum::<usize>();
+        let mut bytes = Vec::with_capacity(length);
+        self.write_to(&mut bytes).expect("writing to a Vec cannot fail");
+        bytes
+    }
 
-        self.header.iter()
-            .chain(chunk_bytes.iter())
-            .copied()
-            .collect()
+    /// Writes the signature and every chunk straight into `writer`.
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<W, PngError> {
+        let mut writer = PngWriter::new(writer)?;
+        for chunk in self.chunks.iter() {
+            writer.write_chunk(chunk)?;
+        }
+        writer.finish()
     }
 }
 
 
 impl TryFrom<&[u8]> for Png {
-    type Error = &'static str;
+    type Error = PngError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        if !value.starts_with(&Png::STANDARD_HEADER) {
-            return Err("Invalid header");
-        }
-        
-        let mut chunks = vec![];
-        let mut i = Png::STANDARD_HEADER.len();
-        while i < value.len() {
-            let remaining_length = value.len() - i;
-            
-            if remaining_length < 4 {
-                return Err("Incomplete chunk");
-            }
-
-            let mut length = [0u8; 4];
-            length.copy_from_slice(&value[i..i+4]);
-            let length = u32::from_be_bytes(length) as usize;
+        let chunks = PngReader::new(value)?.collect::<Result<Vec<Chunk>, PngError>>()?;
 
-            if remaining_length < length + 12 {
-                return Err("Incomplete chunk data");
-            } 
-
-            let chunk_end_index = i + length + 12;
-            chunks.push(Chunk::try_from(&value[i..chunk_end_index])?);
-            
-            i += length + 12;
-        }
-        
         Ok(
             Png {
                 header: Png::STANDARD_HEADER,
-                chunks: chunks,
+                chunks,
             }
         )
     }
 }
 
 
+/// Summarizes the image header, then lists every chunk as the `Chunk`
+/// display shows it.
 impl fmt::Display for Png {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "")
+        match self.header_info() {
+            Ok(ihdr) => write!(f, "{}", ihdr)?,
+            Err(e) => write!(f, "{}", e)?,
+        }
+        write!(f, ", {} chunks", self.chunks().len())?;
+
+        for chunk in self.chunks() {
+            write!(f, "\n{}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Serializes the decoded header, or `null` if it is missing or invalid,
+/// and every chunk along with the byte offset where it starts.
+impl Serialize for Png {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(serde::Serialize)]
+        struct ChunkEntry<'a> {
+            offset: usize,
+            #[serde(flatten)]
+            chunk: &'a Chunk,
+        }
+
+        let mut offset = self.header.len();
+        let chunks: Vec<ChunkEntry> = self.chunks.iter()
+            .map(|chunk| {
+                let entry = ChunkEntry { offset, chunk };
+                offset += chunk.length() as usize + 12;
+                entry
+            })
+            .collect();
+
+        let mut state = serializer.serialize_struct("Png", 2)?;
+        state.serialize_field("header", &self.header_info().ok())?;
+        state.serialize_field("chunks", &chunks)?;
+        state.end()
     }
 }
 
@@ -115,17 +208,17 @@ mod tests {
     use super::*;
     use crate::chunk_type::ChunkType;
     use crate::chunk::Chunk;
+    use crate::filter::FilterStrategy;
+    use crate::ihdr::ColorType;
     use std::str::FromStr;
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -133,7 +226,7 @@ mod tests {
         Png::from_chunks(chunks)
     }
 
-    fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk, &'static str> {
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk, PngError> {
         let chunk_type = ChunkType::from_str(chunk_type)?;
         let data: Vec<u8> = data.bytes().collect();
 
@@ -181,7 +274,22 @@ mod tests {
 
         let png = Png::try_from(bytes.as_ref());
 
-        assert!(png.is_err());
+        assert!(matches!(png, Err(PngError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let bytes = Png::from_chunks(testing_chunks()).as_bytes();
+        let png = Png::try_from(&bytes[..bytes.len() - 5]);
+
+        match png {
+            Err(PngError::Chunk { index, offset, source }) => {
+                assert_eq!(index, 2);
+                assert_eq!(offset, bytes.len() - 31);
+                assert!(matches!(*source, PngError::TruncatedChunk { needed: 31, available: 26 }));
+            }
+            _ => panic!("expected a truncated chunk error"),
+        }
     }
 
     #[test]
@@ -232,6 +340,17 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_chunk() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), &Position::default()).unwrap();
+        png.insert_chunk(chunk_from_strings("tIME", "Before").unwrap(), &Position::BeforeIdat).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "sRGB", "gAMA", "pHYs", "tIME", "IDAT", "RuSt", "TeSt", "IEND"]);
+        assert!(png.validate().is_ok());
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -241,6 +360,92 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_validate() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.validate().is_ok());
+
+        let violations = testing_png().validate().unwrap_err();
+        assert_eq!(violations.len(), 3);
+    }
+
+    #[test]
+    fn test_header_info() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.header_info().unwrap();
+
+        assert_eq!((ihdr.width, ihdr.height), (50, 50));
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+
+        assert!(matches!(testing_png().header_info(), Err(PngError::ChunkNotFound(_))));
+    }
+
+    #[test]
+    fn test_image() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.image().unwrap();
+
+        assert_eq!(image.row_length(), 200);
+        assert_eq!(image.data().len(), 200 * 50);
+        assert!(testing_png().image().is_err());
+    }
+
+    #[test]
+    fn test_replace_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.image().unwrap();
+        let idat = |data: &[u8]| Chunk::new(ChunkType::from_str("IDAT").unwrap(), data.to_vec());
+        png.replace_image_data(vec![idat(&[1]), idat(&[2])]).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "IDAT", "RuSt", "IEND"]);
+        assert_eq!(png.chunks()[5].data(), [2]);
+
+        png.replace_image_data(image.to_idat_chunks(FilterStrategy::Adaptive, 1000).unwrap()).unwrap();
+        assert_eq!(png.image().unwrap(), image);
+        assert!(testing_png().replace_image_data(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_replace_image_data_drops_unsafe_to_copy_chunks() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let idat = Chunk::new(ChunkType::from_str("IDAT").unwrap(), png.chunk_by_type("IDAT").unwrap().data().to_vec());
+        png.insert_chunk(Chunk::new(ChunkType::from_str("siGN").unwrap(), vec![]), &Position::BeforeIend).unwrap();
+        png.insert_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![]), &Position::BeforeIend).unwrap();
+        png.replace_image_data(vec![idat]).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "RuSt", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_display() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let lines: Vec<String> = png.to_string().lines().map(String::from).collect();
+
+        assert_eq!(lines[0], "50x50, 8-bit RGBA, 7 chunks");
+        assert!(lines[1].starts_with("IHDR\t        13 bytes"));
+        assert_eq!(lines[2], "\t50x50, 8-bit RGBA");
+        assert!(lines.contains(&String::from("\tsRGB, perceptual rendering intent")));
+        assert!(lines.contains(&String::from("\tgamma 0.45455")));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let json = serde_json::to_value(&png).unwrap();
+
+        assert_eq!(json["header"]["width"], 50);
+        assert_eq!(json["header"]["color_type"], "Rgba");
+        assert_eq!(json["chunks"][0]["offset"], 8);
+        assert_eq!(json["chunks"][0]["type"]["name"], "IHDR");
+        assert_eq!(json["chunks"][1]["offset"], 8 + 25);
+        assert_eq!(json["chunks"].as_array().unwrap().len(), 7);
+
+        assert!(serde_json::to_value(testing_png()).unwrap()["header"].is_null());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -251,7 +456,7 @@ mod tests {
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
-        let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
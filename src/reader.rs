@@ -0,0 +1,347 @@
+use std::io::{self, Read};
+use std::convert::TryFrom;
+use crc::crc32;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::png::Png;
+
+/// Reads a PNG stream one chunk at a time, so the whole file never
+/// has to be held in memory.
+///
+/// The signature is checked when the reader is created and every
+/// chunk's CRC is checked as the chunk is read.
+pub struct PngReader<R> {
+    reader: R,
+    offset: usize,
+    chunk_offset: usize,
+    index: usize,
+    lenient: bool,
+    failed: bool,
+}
+
+/// Size of the buffer used to stream over the data of skipped chunks.
+const SKIP_BUFFER_SIZE: usize = 8 * 1024;
+
+/// The length and type fields at the start of every chunk.
+struct ChunkHeader {
+    length: usize,
+    chunk_type: ChunkType,
+}
+
+impl<R: Read> PngReader<R> {
+    /// Creates a reader over `reader`, consuming and checking the PNG signature.
+    pub fn new(mut reader: R) -> Result<Self, PngError> {
+        let mut signature = [0u8; 8];
+        if read_fully(&mut reader, &mut signature)? < signature.len() || signature != Png::STANDARD_HEADER {
+            return Err(PngError::InvalidSignature);
+        }
+
+        Ok(
+            PngReader {
+                reader,
+                offset: signature.len(),
+                chunk_offset: signature.len(),
+                index: 0,
+                lenient: false,
+                failed: false,
+            }
+        )
+    }
+
+    /// Accepts chunk types that are not made of ASCII letters and chunks
+    /// whose CRC does not match instead of failing on them, for inspecting
+    /// malformed files.
+    pub fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    /// Reads the next chunk, or returns `None` at the end of the stream.
+    pub fn next_chunk(&mut self) -> Result<Option<Chunk>, PngError> {
+        self.read_chunk(|_| true).map(Option::flatten)
+    }
+
+    /// Byte offset in the stream where the chunk last returned starts.
+    pub fn chunk_offset(&self) -> usize {
+        self.chunk_offset
+    }
+
+    /// Reads chunks until one of type `chunk_type` is found.
+    /// The data of the chunks skipped along the way is checked against
+    /// their CRC but never buffered.
+    pub fn find_chunk(&mut self, chunk_type: &str) -> Result<Option<Chunk>, PngError> {
+        while let Some(chunk) = self.read_chunk(|t| t.to_string() == chunk_type)? {
+            if chunk.is_some() {
+                return Ok(chunk);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the next chunk, keeping its data only if `keep` returns `true`
+    /// for its type. Returns `None` at the end of the stream and `Some(None)`
+    /// for a chunk that was skipped.
+    fn read_chunk<F>(&mut self, keep: F) -> Result<Option<Option<Chunk>>, PngError>
+    where
+        F: Fn(&ChunkType) -> bool,
+    {
+        if self.failed {
+            return Ok(None);
+        }
+
+        let (index, offset) = (self.index, self.offset);
+        let result = self.read_header().and_then(|header| match header {
+            Some(header) if keep(&header.chunk_type) => self.read_data(header).map(|c| Some(Some(c))),
+            Some(header) => self.skip_data(header).map(|_| Some(None)),
+            None => Ok(None),
+        });
+
+        match result {
+            Ok(chunk) => {
+                self.chunk_offset = offset;
+                Ok(chunk)
+            }
+            Err(e) => {
+                self.failed = true;
+                Err(e.at_chunk(index, offset))
+            }
+        }
+    }
+
+    fn read_header(&mut self) -> Result<Option<ChunkHeader>, PngError> {
+        let mut header = [0u8; 8];
+        let read = read_fully(&mut self.reader, &mut header)?;
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        if read < header.len() {
+            return Err(PngError::TruncatedChunk { needed: 12, available: read });
+        }
+
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&header[0..4]);
+        let length = u32::from_be_bytes(length) as usize;
+        if length > Chunk::MAX_LENGTH {
+            return Err(PngError::ChunkTooLarge(length));
+        }
+
+        let mut chunk_type = [0u8; 4];
+        chunk_type.copy_from_slice(&header[4..8]);
+        let chunk_type = if self.lenient {
+            ChunkType::from_bytes_lenient(chunk_type)
+        }
+        else {
+            ChunkType::try_from(chunk_type)?
+        };
+
+        Ok(
+            Some(ChunkHeader {
+                length,
+                chunk_type,
+            })
+        )
+    }
+
+    fn read_data(&mut self, header: ChunkHeader) -> Result<Chunk, PngError> {
+        let mut data = Vec::new();
+        (&mut self.reader).take(header.length as u64).read_to_end(&mut data)?;
+
+        let crc = self.read_crc(&header, data.len())?;
+        self.finish_chunk(header.length);
+
+        if self.lenient {
+            Ok(Chunk::from_parts_unchecked(header.chunk_type, data, crc))
+        }
+        else {
+            Chunk::from_parts(header.chunk_type, data, crc)
+        }
+    }
+
+    fn skip_data(&mut self, header: ChunkHeader) -> Result<(), PngError> {
+        let mut buffer = [0u8; SKIP_BUFFER_SIZE];
+        let mut checksum = crc32::update(0, &crc32::IEEE_TABLE, header.chunk_type.bytes());
+        let mut remaining = header.length;
+
+        while remaining > 0 {
+            let len = remaining.min(buffer.len());
+            let read = read_fully(&mut self.reader, &mut buffer[..len])?;
+            checksum = crc32::update(checksum, &crc32::IEEE_TABLE, &buffer[..read]);
+            remaining -= read;
+
+            if read < len {
+                break;
+            }
+        }
+
+        let crc = self.read_crc(&header, header.length - remaining)?;
+        if crc != checksum && !self.lenient {
+            return Err(PngError::CrcMismatch { expected: checksum, actual: crc });
+        }
+
+        self.finish_chunk(header.length);
+        Ok(())
+    }
+
+    /// Reads the CRC that follows the chunk data, given how many bytes
+    /// of data were actually available.
+    fn read_crc(&mut self, header: &ChunkHeader, data_read: usize) -> Result<u32, PngError> {
+        let needed = header.length + 12;
+        if data_read < header.length {
+            return Err(PngError::TruncatedChunk { needed, available: 8 + data_read });
+        }
+
+        let mut crc = [0u8; 4];
+        let read = read_fully(&mut self.reader, &mut crc)?;
+        if read < crc.len() {
+            return Err(PngError::TruncatedChunk { needed, available: 8 + data_read + read });
+        }
+
+        Ok(u32::from_be_bytes(crc))
+    }
+
+    fn finish_chunk(&mut self, length: usize) {
+        self.offset += length + 12;
+        self.index += 1;
+    }
+}
+
+
+impl<R: Read> Iterator for PngReader<R> {
+    type Item = Result<Chunk, PngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk().transpose()
+    }
+}
+
+
+/// Reads into `buf` until it is full or the stream ends,
+/// returning the number of bytes read.
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![42; 20_000]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ]
+    }
+
+    fn testing_bytes() -> Vec<u8> {
+        Png::from_chunks(testing_chunks()).as_bytes()
+    }
+
+    #[test]
+    fn test_iterate_chunks() {
+        let bytes = testing_bytes();
+        let chunks: Vec<Chunk> = PngReader::new(&bytes[..]).unwrap().collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(chunks.len(), 4);
+        for (actual, expected) in chunks.iter().zip(testing_chunks().iter()) {
+            assert_eq!(actual.as_bytes(), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_find_chunk() {
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+
+        let chunk = reader.find_chunk("ruSt").unwrap().unwrap();
+        assert_eq!(chunk.data_as_string().unwrap(), "hello");
+        assert_eq!(reader.chunk_offset(), 8 + 25 + 20_012);
+        assert_eq!(reader.next_chunk().unwrap().unwrap().chunk_type().to_string(), "IEND");
+        assert!(reader.next_chunk().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_missing_chunk() {
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+
+        assert!(reader.find_chunk("abCd").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_skipped_chunk_crc_is_checked() {
+        let mut bytes = testing_bytes();
+        // Flip a byte in the middle of the IDAT data.
+        bytes[8 + 25 + 100] ^= 1;
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        match reader.find_chunk("ruSt") {
+            Err(PngError::Chunk { index, offset, source }) => {
+                assert_eq!(index, 1);
+                assert_eq!(offset, 8 + 25);
+                assert!(matches!(*source, PngError::CrcMismatch { .. }));
+            }
+            _ => panic!("expected a CRC mismatch"),
+        }
+        assert!(reader.next_chunk().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_chunk_type() {
+        let mut chunks = testing_chunks();
+        chunks.insert(1, Chunk::new(ChunkType::from_bytes_lenient([b'r', b'u', 0, b't']), vec![1, 2]));
+        let bytes = Png::from_chunks(chunks).as_bytes();
+
+        let result: Result<Vec<Chunk>, _> = PngReader::new(&bytes[..]).unwrap().collect();
+        match result {
+            Err(PngError::Chunk { index, source, .. }) => {
+                assert_eq!(index, 1);
+                assert!(matches!(*source, PngError::InvalidChunkType([b'r', b'u', 0, b't'])));
+            }
+            _ => panic!("expected an invalid chunk type"),
+        }
+
+        let chunks: Vec<Chunk> = PngReader::new(&bytes[..]).unwrap().lenient().collect::<Result<_, _>>().unwrap();
+        assert_eq!(chunks[1].chunk_type().bytes(), &[b'r', b'u', 0, b't']);
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let mut bytes = testing_bytes();
+        bytes[0] = 13;
+
+        assert!(matches!(PngReader::new(&bytes[..]), Err(PngError::InvalidSignature)));
+        assert!(matches!(PngReader::new(&bytes[..4]), Err(PngError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(&bytes[..1000]).unwrap();
+
+        reader.next_chunk().unwrap();
+        match reader.next_chunk() {
+            Err(PngError::Chunk { source, .. }) => {
+                assert!(matches!(*source, PngError::TruncatedChunk { needed: 20_012, available: 967 }));
+            }
+            _ => panic!("expected a truncated chunk"),
+        }
+    }
+}
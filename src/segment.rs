@@ -0,0 +1,133 @@
+use crate::error::PngError;
+
+/// Size of the header at the start of every segment: the 1-based
+/// sequence number and the total number of segments, as big-endian `u16`s.
+pub const HEADER_LENGTH: usize = 4;
+
+/// Splits `message` into segments of at most `max_size` bytes, header
+/// included. A message that fits in one segment still gets a header, so
+/// readers never have to guess whether a chunk was split.
+pub fn split(message: &[u8], max_size: usize) -> Result<Vec<Vec<u8>>, PngError> {
+    if max_size <= HEADER_LENGTH {
+        return Err(PngError::Segment(format!("segments must be larger than the {} byte header", HEADER_LENGTH)));
+    }
+
+    let payload_size = max_size - HEADER_LENGTH;
+    let total = message.len().div_ceil(payload_size).max(1);
+    if total > u16::MAX as usize {
+        return Err(PngError::Segment(format!("message needs {} segments, at most {} are allowed", total, u16::MAX)));
+    }
+
+    let payloads: Vec<&[u8]> = if message.is_empty() { vec![message] } else { message.chunks(payload_size).collect() };
+    let segments = payloads.into_iter()
+        .enumerate()
+        .map(|(i, payload)| {
+            let mut segment = Vec::with_capacity(HEADER_LENGTH + payload.len());
+            segment.extend_from_slice(&(i as u16 + 1).to_be_bytes());
+            segment.extend_from_slice(&(total as u16).to_be_bytes());
+            segment.extend_from_slice(payload);
+            segment
+        })
+        .collect();
+
+    Ok(segments)
+}
+
+/// Reassembles the output of `split`, checking that every segment is
+/// present exactly once, in order, and that all agree on the total.
+pub fn join<'a, I>(segments: I) -> Result<Vec<u8>, PngError>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut message = Vec::new();
+    let mut expected_total = None;
+    let mut count = 0;
+
+    for segment in segments {
+        if segment.len() < HEADER_LENGTH {
+            return Err(PngError::Segment(format!("segment {} is shorter than its header", count + 1)));
+        }
+
+        let sequence = u16::from_be_bytes([segment[0], segment[1]]) as usize;
+        let total = u16::from_be_bytes([segment[2], segment[3]]) as usize;
+
+        if *expected_total.get_or_insert(total) != total {
+            return Err(PngError::Segment(format!("segment {} claims {} segments, others claim {}", sequence, total, expected_total.unwrap_or(0))));
+        }
+        if sequence != count + 1 {
+            return Err(PngError::Segment(format!("expected segment {}, found segment {}", count + 1, sequence)));
+        }
+        if sequence > total {
+            return Err(PngError::Segment(format!("found segment {} of {}", sequence, total)));
+        }
+
+        message.extend_from_slice(&segment[HEADER_LENGTH..]);
+        count += 1;
+    }
+
+    match expected_total {
+        None => Err(PngError::Segment(String::from("no segments found"))),
+        Some(total) if count < total => Err(PngError::Segment(format!("found {} of {} segments", count, total))),
+        Some(_) => Ok(message),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join_vecs(segments: &[Vec<u8>]) -> Result<Vec<u8>, PngError> {
+        join(segments.iter().map(Vec::as_slice))
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let segments = split(b"hello world", 8).unwrap();
+
+        assert_eq!(segments, [b"\0\x01\0\x03hell".to_vec(), b"\0\x02\0\x03o wo".to_vec(), b"\0\x03\0\x03rld".to_vec()]);
+        assert_eq!(join_vecs(&segments).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn test_single_segment() {
+        assert_eq!(split(b"hello", 100).unwrap(), [b"\0\x01\0\x01hello".to_vec()]);
+        assert_eq!(split(b"", 100).unwrap(), [b"\0\x01\0\x01".to_vec()]);
+        assert_eq!(join_vecs(&split(b"", 100).unwrap()).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_max_size_too_small() {
+        assert!(split(b"hello", HEADER_LENGTH).is_err());
+        assert!(split(b"hello", HEADER_LENGTH + 1).is_ok());
+    }
+
+    #[test]
+    fn test_missing_segment() {
+        let mut segments = split(b"hello world", 8).unwrap();
+        segments.pop();
+        assert!(matches!(join_vecs(&segments), Err(PngError::Segment(_))));
+
+        segments.remove(0);
+        assert!(join_vecs(&segments).is_err());
+        assert!(join_vecs(&[]).is_err());
+    }
+
+    #[test]
+    fn test_out_of_order_or_duplicated() {
+        let mut segments = split(b"hello world", 8).unwrap();
+        segments.swap(0, 1);
+        assert!(join_vecs(&segments).is_err());
+
+        let mut segments = split(b"hello world", 8).unwrap();
+        segments.insert(1, segments[0].clone());
+        assert!(join_vecs(&segments).is_err());
+    }
+
+    #[test]
+    fn test_inconsistent_total() {
+        let mut segments = split(b"hello world", 8).unwrap();
+        segments.extend(split(b"other", 100).unwrap());
+        assert!(join_vecs(&segments).is_err());
+    }
+}
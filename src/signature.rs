@@ -0,0 +1,180 @@
+use std::convert::TryFrom;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, SIGNATURE_LENGTH};
+use sha2::{Digest, Sha256};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::png::Png;
+
+/// A `siGN` chunk: an Ed25519 signature over the critical chunks of the
+/// image and the ancillary chunk types listed in it.
+///
+/// The type is ancillary, private and unsafe to copy, so editors that
+/// change the image data drop it instead of keeping a stale signature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureChunk {
+    covered: Vec<ChunkType>,
+    signature: Signature,
+}
+
+impl SignatureChunk {
+    pub const CHUNK_TYPE: [u8; 4] = *b"siGN";
+
+    /// Prefix of the signed digest, so the signature cannot be replayed
+    /// in another context.
+    const DOMAIN: &'static [u8] = b"png-msg signature v1";
+
+    /// Signs the critical chunks of `png` and every chunk whose type is in `covered`.
+    pub fn sign(png: &Png, covered: &[ChunkType], key: &SigningKey) -> Result<Self, PngError> {
+        if let Some(chunk_type) = covered.iter().find(|t| t.is_critical() || t.bytes() == &SignatureChunk::CHUNK_TYPE) {
+            return Err(PngError::Signature(format!("{} cannot be listed as a covered ancillary chunk", chunk_type)));
+        }
+        if covered.len() > u8::MAX as usize {
+            return Err(PngError::Signature(format!("at most 255 ancillary chunk types can be covered, got {}", covered.len())));
+        }
+
+        let covered = covered.to_vec();
+        let signature = key.sign(&digest(png.chunks(), &covered));
+        Ok(SignatureChunk { covered, signature })
+    }
+
+    /// Ancillary chunk types covered by the signature, besides the critical chunks.
+    pub fn covered(&self) -> &[ChunkType] {
+        &self.covered
+    }
+
+    /// Recomputes the digest of `png` and checks the signature against it.
+    pub fn verify(&self, png: &Png, key: &VerifyingKey) -> Result<(), PngError> {
+        key.verify(&digest(png.chunks(), &self.covered), &self.signature)
+            .map_err(|_| PngError::Signature(String::from("the signature does not match the image or the key")))
+    }
+
+    /// Serializes the covered chunk types and the signature into a `siGN` chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = vec![self.covered.len() as u8];
+        for chunk_type in self.covered.iter() {
+            data.extend_from_slice(chunk_type.bytes());
+        }
+        data.extend_from_slice(&self.signature.to_bytes());
+
+        Chunk::new(ChunkType { bytes: SignatureChunk::CHUNK_TYPE }, data)
+    }
+}
+
+
+impl TryFrom<&Chunk> for SignatureChunk {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if chunk.chunk_type().bytes() != &SignatureChunk::CHUNK_TYPE {
+            return Err(PngError::Signature(format!("expected a siGN chunk, got {}", chunk.chunk_type())));
+        }
+
+        let data = chunk.data();
+        let count = *data.first().unwrap_or(&0) as usize;
+        if data.len() != 1 + count * 4 + SIGNATURE_LENGTH {
+            return Err(PngError::Signature(format!("unexpected siGN chunk length {}", data.len())));
+        }
+
+        let covered = data[1..1 + count * 4]
+            .chunks(4)
+            .map(|bytes| ChunkType::try_from([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut signature = [0u8; SIGNATURE_LENGTH];
+        signature.copy_from_slice(&data[1 + count * 4..]);
+
+        Ok(
+            SignatureChunk {
+                covered,
+                signature: Signature::from_bytes(&signature),
+            }
+        )
+    }
+}
+
+
+/// Hashes the covered chunk types, then the length, type and data of every
+/// critical chunk and every chunk of a covered type, in file order.
+fn digest(chunks: &[Chunk], covered: &[ChunkType]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(SignatureChunk::DOMAIN);
+    hasher.update([covered.len() as u8]);
+    for chunk_type in covered.iter() {
+        hasher.update(chunk_type.bytes());
+    }
+
+    for chunk in chunks.iter().filter(|c| c.chunk_type().is_critical() || covered.contains(c.chunk_type())) {
+        hasher.update(chunk.length().to_be_bytes());
+        hasher.update(chunk.chunk_type().bytes());
+        hasher.update(chunk.data());
+    }
+
+    hasher.finalize().into()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chacha20poly1305::aead::OsRng;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]),
+            Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Author\0Build".to_vec()),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![1, 2, 3]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ])
+    }
+
+    fn covered() -> Vec<ChunkType> {
+        vec![ChunkType::from_str("tEXt").unwrap()]
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::generate(&mut OsRng);
+        let signature = SignatureChunk::sign(&testing_png(), &covered(), &key).unwrap();
+
+        let parsed = SignatureChunk::try_from(&signature.to_chunk()).unwrap();
+        assert_eq!(parsed, signature);
+        assert_eq!(parsed.covered(), &covered()[..]);
+        assert!(parsed.verify(&testing_png(), &key.verifying_key()).is_ok());
+
+        let other_key = SigningKey::generate(&mut OsRng);
+        assert!(matches!(parsed.verify(&testing_png(), &other_key.verifying_key()), Err(PngError::Signature(_))));
+    }
+
+    #[test]
+    fn test_only_covered_chunks_are_signed() {
+        let key = SigningKey::generate(&mut OsRng);
+        let signature = SignatureChunk::sign(&testing_png(), &covered(), &key).unwrap();
+
+        let mut png = testing_png();
+        png.remove_chunk("ruSt").unwrap();
+        assert!(signature.verify(&png, &key.verifying_key()).is_ok());
+
+        png.remove_chunk("tEXt").unwrap();
+        assert!(signature.verify(&png, &key.verifying_key()).is_err());
+
+        let mut png = testing_png();
+        png.remove_chunk("IDAT").unwrap();
+        assert!(signature.verify(&png, &key.verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_cannot_cover_critical_chunks() {
+        let key = SigningKey::generate(&mut OsRng);
+        let covered = [ChunkType::from_str("IDAT").unwrap()];
+        assert!(SignatureChunk::sign(&testing_png(), &covered, &key).is_err());
+    }
+
+    #[test]
+    fn test_malformed_chunk() {
+        let chunk = Chunk::new(ChunkType::from_str("siGN").unwrap(), vec![1, b't', b'E', b'X', b't']);
+        assert!(matches!(SignatureChunk::try_from(&chunk), Err(PngError::Signature(_))));
+    }
+}
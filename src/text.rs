@@ -0,0 +1,594 @@
+use std::convert::TryFrom;
+use std::fmt;
+use serde::Serialize;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::zlib;
+
+/// A `tEXt` chunk: a keyword and an uncompressed Latin-1 text.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TextChunk {
+    keyword: String,
+    text: String,
+}
+
+impl TextChunk {
+    pub const CHUNK_TYPE: [u8; 4] = *b"tEXt";
+
+    /// Creates a text entry, checking that the keyword is valid and that
+    /// both strings can be represented in Latin-1.
+    pub fn new(keyword: &str, text: &str) -> Result<Self, PngError> {
+        check_latin1_entry(keyword, text)?;
+
+        Ok(
+            TextChunk {
+                keyword: keyword.to_string(),
+                text: text.to_string(),
+            }
+        )
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Serializes the entry into a `tEXt` chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = to_latin1(&self.keyword).expect("keyword was checked on creation");
+        data.push(0);
+        data.extend(to_latin1(&self.text).expect("text was checked on creation"));
+
+        Chunk::new(ChunkType { bytes: TextChunk::CHUNK_TYPE }, data)
+    }
+}
+
+
+impl TryFrom<&Chunk> for TextChunk {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if chunk.chunk_type().bytes() != &TextChunk::CHUNK_TYPE {
+            return Err(PngError::InvalidTextChunk(format!("expected a tEXt chunk, got {}", chunk.chunk_type())));
+        }
+
+        let (keyword, text) = split_keyword(chunk.data())?;
+        TextChunk::new(&from_latin1(keyword), &from_latin1(text))
+    }
+}
+
+
+/// A `zTXt` chunk: a keyword and a Latin-1 text stored as a zlib stream.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CompressedTextChunk {
+    keyword: String,
+    text: String,
+}
+
+impl CompressedTextChunk {
+    pub const CHUNK_TYPE: [u8; 4] = *b"zTXt";
+
+    /// Creates a compressed text entry, with the same rules as `tEXt`.
+    pub fn new(keyword: &str, text: &str) -> Result<Self, PngError> {
+        check_latin1_entry(keyword, text)?;
+
+        Ok(
+            CompressedTextChunk {
+                keyword: keyword.to_string(),
+                text: text.to_string(),
+            }
+        )
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Serializes the entry into a `zTXt` chunk, compressing the text.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = to_latin1(&self.keyword).expect("keyword was checked on creation");
+        data.push(0);
+        data.push(COMPRESSION_METHOD_DEFLATE);
+        data.extend(zlib::compress(&to_latin1(&self.text).expect("text was checked on creation")));
+
+        Chunk::new(ChunkType { bytes: CompressedTextChunk::CHUNK_TYPE }, data)
+    }
+}
+
+
+impl TryFrom<&Chunk> for CompressedTextChunk {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if chunk.chunk_type().bytes() != &CompressedTextChunk::CHUNK_TYPE {
+            return Err(PngError::InvalidTextChunk(format!("expected a zTXt chunk, got {}", chunk.chunk_type())));
+        }
+
+        let (keyword, rest) = split_keyword(chunk.data())?;
+        let (&method, compressed) = rest.split_first()
+            .ok_or_else(|| PngError::InvalidTextChunk(String::from("missing compression method")))?;
+        check_compression_method(method)?;
+
+        let text = zlib::decompress(compressed, MAX_TEXT_LENGTH)?;
+        CompressedTextChunk::new(&from_latin1(keyword), &from_latin1(&text))
+    }
+}
+
+
+/// An `iTXt` chunk: a keyword and a UTF-8 text, optionally compressed and
+/// tagged with a language and a translation of the keyword.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InternationalTextChunk {
+    keyword: String,
+    compressed: bool,
+    language_tag: String,
+    translated_keyword: String,
+    text: String,
+}
+
+impl InternationalTextChunk {
+    pub const CHUNK_TYPE: [u8; 4] = *b"iTXt";
+
+    /// Creates an uncompressed entry with no language information.
+    pub fn new(keyword: &str, text: &str) -> Result<Self, PngError> {
+        check_keyword(keyword)?;
+        if text.contains('\0') {
+            return Err(PngError::InvalidTextChunk(String::from("text must not contain null characters")));
+        }
+
+        Ok(
+            InternationalTextChunk {
+                keyword: keyword.to_string(),
+                compressed: false,
+                language_tag: String::new(),
+                translated_keyword: String::new(),
+                text: text.to_string(),
+            }
+        )
+    }
+
+    /// Tags the text with a language (e.g. `de-CH`) and the keyword
+    /// translated into that language.
+    pub fn with_language(mut self, language_tag: &str, translated_keyword: &str) -> Result<Self, PngError> {
+        check_language_tag(language_tag)?;
+        if translated_keyword.contains('\0') {
+            return Err(PngError::InvalidTextChunk(String::from("translated keyword must not contain null characters")));
+        }
+
+        self.language_tag = language_tag.to_string();
+        self.translated_keyword = translated_keyword.to_string();
+        Ok(self)
+    }
+
+    /// Stores the text compressed.
+    pub fn compressed(mut self) -> Self {
+        self.compressed = true;
+        self
+    }
+
+    pub fn keyword(&self) -> &str {
+        &self.keyword
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// The language of the text, empty if unknown.
+    pub fn language_tag(&self) -> &str {
+        &self.language_tag
+    }
+
+    /// The keyword in the language of the text, empty if not given.
+    pub fn translated_keyword(&self) -> &str {
+        &self.translated_keyword
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Serializes the entry into an `iTXt` chunk.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = to_latin1(&self.keyword).expect("keyword was checked on creation");
+        data.push(0);
+        data.push(self.compressed as u8);
+        data.push(COMPRESSION_METHOD_DEFLATE);
+        data.extend_from_slice(self.language_tag.as_bytes());
+        data.push(0);
+        data.extend_from_slice(self.translated_keyword.as_bytes());
+        data.push(0);
+
+        if self.compressed {
+            data.extend(zlib::compress(self.text.as_bytes()));
+        }
+        else {
+            data.extend_from_slice(self.text.as_bytes());
+        }
+
+        Chunk::new(ChunkType { bytes: InternationalTextChunk::CHUNK_TYPE }, data)
+    }
+}
+
+
+impl TryFrom<&Chunk> for InternationalTextChunk {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if chunk.chunk_type().bytes() != &InternationalTextChunk::CHUNK_TYPE {
+            return Err(PngError::InvalidTextChunk(format!("expected an iTXt chunk, got {}", chunk.chunk_type())));
+        }
+
+        let (keyword, rest) = split_keyword(chunk.data())?;
+        if rest.len() < 2 {
+            return Err(PngError::InvalidTextChunk(String::from("missing compression flag and method")));
+        }
+
+        let compressed = match rest[0] {
+            0 => false,
+            1 => true,
+            other => return Err(PngError::InvalidTextChunk(format!("invalid compression flag {}", other))),
+        };
+        check_compression_method(rest[1])?;
+
+        let (language_tag, rest) = split_keyword(&rest[2..])?;
+        let (translated_keyword, text) = split_keyword(rest)?;
+        let text = if compressed { zlib::decompress(text, MAX_TEXT_LENGTH)? } else { text.to_vec() };
+
+        let entry = InternationalTextChunk::new(&from_latin1(keyword), &from_utf8(text)?)?
+            .with_language(&from_utf8(language_tag.to_vec())?, &from_utf8(translated_keyword.to_vec())?)?;
+        Ok(if compressed { entry.compressed() } else { entry })
+    }
+}
+
+
+/// Any of the textual chunk types, for code that only cares about
+/// keywords and text.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum TextEntry {
+    Text(TextChunk),
+    Compressed(CompressedTextChunk),
+    International(InternationalTextChunk),
+}
+
+impl TextEntry {
+    /// Chunk types that hold textual entries.
+    pub const CHUNK_TYPES: [[u8; 4]; 3] = [
+        TextChunk::CHUNK_TYPE,
+        CompressedTextChunk::CHUNK_TYPE,
+        InternationalTextChunk::CHUNK_TYPE,
+    ];
+
+    /// Creates an entry that will be stored in a chunk of `chunk_type`.
+    pub fn new(chunk_type: &ChunkType, keyword: &str, text: &str) -> Result<Self, PngError> {
+        match *chunk_type.bytes() {
+            TextChunk::CHUNK_TYPE => Ok(TextEntry::Text(TextChunk::new(keyword, text)?)),
+            CompressedTextChunk::CHUNK_TYPE => Ok(TextEntry::Compressed(CompressedTextChunk::new(keyword, text)?)),
+            InternationalTextChunk::CHUNK_TYPE => Ok(TextEntry::International(InternationalTextChunk::new(keyword, text)?)),
+            _ => Err(PngError::InvalidTextChunk(format!("{} is not a textual chunk type", chunk_type))),
+        }
+    }
+
+    /// Whether chunks of `chunk_type` hold textual entries.
+    pub fn is_textual(chunk_type: &ChunkType) -> bool {
+        TextEntry::CHUNK_TYPES.contains(chunk_type.bytes())
+    }
+
+    pub fn keyword(&self) -> &str {
+        match self {
+            TextEntry::Text(text) => text.keyword(),
+            TextEntry::Compressed(text) => text.keyword(),
+            TextEntry::International(text) => text.keyword(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            TextEntry::Text(text) => text.text(),
+            TextEntry::Compressed(text) => text.text(),
+            TextEntry::International(text) => text.text(),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        match self {
+            TextEntry::Text(text) => text.to_chunk(),
+            TextEntry::Compressed(text) => text.to_chunk(),
+            TextEntry::International(text) => text.to_chunk(),
+        }
+    }
+}
+
+
+impl TryFrom<&Chunk> for TextEntry {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        match *chunk.chunk_type().bytes() {
+            TextChunk::CHUNK_TYPE => Ok(TextEntry::Text(TextChunk::try_from(chunk)?)),
+            CompressedTextChunk::CHUNK_TYPE => Ok(TextEntry::Compressed(CompressedTextChunk::try_from(chunk)?)),
+            InternationalTextChunk::CHUNK_TYPE => Ok(TextEntry::International(InternationalTextChunk::try_from(chunk)?)),
+            _ => Err(PngError::InvalidTextChunk(format!("{} is not a textual chunk type", chunk.chunk_type()))),
+        }
+    }
+}
+
+
+impl fmt::Display for TextEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.keyword())?;
+        if let TextEntry::International(text) = self {
+            let details: Vec<&str> = [text.language_tag(), text.translated_keyword()].iter()
+                .copied()
+                .filter(|s| !s.is_empty())
+                .chain(if text.is_compressed() { Some("compressed") } else { None })
+                .collect();
+            if !details.is_empty() {
+                write!(f, " ({})", details.join(", "))?;
+            }
+        }
+        write!(f, ": {}", self.text())
+    }
+}
+
+
+/// The only compression method the specification defines: zlib deflate.
+pub(crate) const COMPRESSION_METHOD_DEFLATE: u8 = 0;
+
+/// Longest text a compressed entry may inflate to when it is read.
+const MAX_TEXT_LENGTH: usize = 1 << 24;
+
+pub(crate) fn check_compression_method(method: u8) -> Result<(), PngError> {
+    if method != COMPRESSION_METHOD_DEFLATE {
+        return Err(PngError::InvalidTextChunk(format!("unknown compression method {}", method)));
+    }
+    Ok(())
+}
+
+/// Splits chunk data at the null separator that ends the keyword.
+pub(crate) fn split_keyword(data: &[u8]) -> Result<(&[u8], &[u8]), PngError> {
+    match data.iter().position(|&b| b == 0) {
+        Some(i) => Ok((&data[..i], &data[i + 1..])),
+        None => Err(PngError::InvalidTextChunk(String::from("missing null separator after keyword"))),
+    }
+}
+
+/// Checks the keyword rules shared by all textual chunks: 1 to 79
+/// printable Latin-1 characters, with no leading, trailing or
+/// consecutive spaces.
+pub(crate) fn check_keyword(keyword: &str) -> Result<(), PngError> {
+    let bytes = to_latin1(keyword)?;
+
+    if bytes.is_empty() || bytes.len() > 79 {
+        return Err(PngError::InvalidTextChunk(format!("keyword must be 1 to 79 characters long, got {}", bytes.len())));
+    }
+
+    if let Some(&b) = bytes.iter().find(|&&b| !(32..=126).contains(&b) && b < 161) {
+        return Err(PngError::InvalidTextChunk(format!("keyword contains non-printable character {:#04x}", b)));
+    }
+
+    if bytes.starts_with(b" ") || bytes.ends_with(b" ") || bytes.windows(2).any(|w| w == b"  ") {
+        return Err(PngError::InvalidTextChunk(String::from("keyword has leading, trailing or consecutive spaces")));
+    }
+
+    Ok(())
+}
+
+/// Checks the rules `tEXt` and `zTXt` entries share: a valid keyword
+/// and a text of Latin-1 characters other than null.
+pub(crate) fn check_latin1_entry(keyword: &str, text: &str) -> Result<(), PngError> {
+    check_keyword(keyword)?;
+    if to_latin1(text)?.contains(&0) {
+        return Err(PngError::InvalidTextChunk(String::from("text must not contain null characters")));
+    }
+    Ok(())
+}
+
+/// Encodes `s` as Latin-1, failing on characters outside U+0000..U+00FF.
+pub(crate) fn to_latin1(s: &str) -> Result<Vec<u8>, PngError> {
+    s.chars()
+        .map(|c| match u8::try_from(c as u32) {
+            Ok(b) => Ok(b),
+            Err(_) => Err(PngError::InvalidTextChunk(format!("{:?} cannot be represented in Latin-1", c))),
+        })
+        .collect()
+}
+
+/// Checks that a language tag is made of 1 to 8 character ASCII
+/// alphanumeric words separated by hyphens, as in RFC 3066.
+/// An empty tag means the language is unknown.
+pub(crate) fn check_language_tag(tag: &str) -> Result<(), PngError> {
+    let valid_word = |word: &str| (1..=8).contains(&word.len()) && word.bytes().all(|b| b.is_ascii_alphanumeric());
+    if !tag.is_empty() && !tag.split('-').all(valid_word) {
+        return Err(PngError::InvalidTextChunk(format!("invalid language tag {:?}", tag)));
+    }
+    Ok(())
+}
+
+fn from_utf8(bytes: Vec<u8>) -> Result<String, PngError> {
+    String::from_utf8(bytes).map_err(|e| PngError::InvalidTextChunk(e.to_string()))
+}
+
+/// Decodes Latin-1 bytes, each of which maps to the code point of the same value.
+pub(crate) fn from_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_text_round_trip() {
+        let text = TextChunk::new("Comment", "Café olé").unwrap();
+        let chunk = text.to_chunk();
+
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Comment\0Caf\xe9 ol\xe9");
+        assert_eq!(TextChunk::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_text_from_chunk() {
+        let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Title\0Dice".to_vec());
+        let text = TextChunk::try_from(&chunk).unwrap();
+
+        assert_eq!(text.keyword(), "Title");
+        assert_eq!(text.text(), "Dice");
+    }
+
+    #[test]
+    fn test_missing_separator() {
+        let chunk = Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"Title".to_vec());
+        assert!(matches!(TextChunk::try_from(&chunk), Err(PngError::InvalidTextChunk(_))));
+    }
+
+    #[test]
+    fn test_wrong_chunk_type() {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"Title\0Dice".to_vec());
+        assert!(TextChunk::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_keyword_length() {
+        assert!(TextChunk::new("", "text").is_err());
+        assert!(TextChunk::new(&"k".repeat(79), "text").is_ok());
+        assert!(TextChunk::new(&"k".repeat(80), "text").is_err());
+    }
+
+    #[test]
+    fn test_keyword_characters() {
+        assert!(TextChunk::new("Créé", "text").is_ok());
+        assert!(TextChunk::new("Tab\tbed", "text").is_err());
+        assert!(TextChunk::new("\u{a0}nbsp", "text").is_err());
+        assert!(TextChunk::new(" Title", "text").is_err());
+        assert!(TextChunk::new("Title ", "text").is_err());
+        assert!(TextChunk::new("Two  spaces", "text").is_err());
+        assert!(TextChunk::new("Two spaces", "text").is_ok());
+    }
+
+    #[test]
+    fn test_text_must_be_latin1() {
+        assert!(TextChunk::new("Title", "snow ☃").is_err());
+        assert!(TextChunk::new("Title", "null\0byte").is_err());
+        assert!(TextChunk::new("Title", "line\nbreak").is_ok());
+    }
+
+    #[test]
+    fn test_compressed_text_rejects_nulls() {
+        assert!(CompressedTextChunk::new("Comment", "null\0byte").is_err());
+
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(zlib::compress(b"null\0byte"));
+        let chunk = Chunk::new(ChunkType::from_str("zTXt").unwrap(), data);
+        assert!(matches!(CompressedTextChunk::try_from(&chunk), Err(PngError::InvalidTextChunk(_))));
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let long_text = "Café olé! ".repeat(100);
+        let text = CompressedTextChunk::new("Comment", &long_text).unwrap();
+        let chunk = text.to_chunk();
+
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        assert!(chunk.data().starts_with(b"Comment\0\0"));
+        assert!(chunk.data().len() < long_text.len());
+        assert_eq!(CompressedTextChunk::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_compressed_unknown_method() {
+        let mut data = b"Comment\0\x01".to_vec();
+        data.extend(zlib::compress(b"text"));
+        let chunk = Chunk::new(ChunkType::from_str("zTXt").unwrap(), data);
+
+        assert!(matches!(CompressedTextChunk::try_from(&chunk), Err(PngError::InvalidTextChunk(_))));
+    }
+
+    #[test]
+    fn test_compressed_corrupt_stream() {
+        let chunk = Chunk::new(ChunkType::from_str("zTXt").unwrap(), b"Comment\0\0garbage".to_vec());
+        assert!(matches!(CompressedTextChunk::try_from(&chunk), Err(PngError::Zlib(_))));
+
+        let chunk = Chunk::new(ChunkType::from_str("zTXt").unwrap(), b"Comment\0".to_vec());
+        assert!(matches!(CompressedTextChunk::try_from(&chunk), Err(PngError::InvalidTextChunk(_))));
+    }
+
+    #[test]
+    fn test_text_entry() {
+        let ztxt = ChunkType::from_str("zTXt").unwrap();
+        let entry = TextEntry::new(&ztxt, "Title", "Dice").unwrap();
+        let parsed = TextEntry::try_from(&entry.to_chunk()).unwrap();
+
+        assert!(matches!(parsed, TextEntry::Compressed(_)));
+        assert_eq!((parsed.keyword(), parsed.text()), ("Title", "Dice"));
+        assert!(TextEntry::is_textual(&ztxt));
+        assert!(!TextEntry::is_textual(&ChunkType::from_str("ruSt").unwrap()));
+        assert!(TextEntry::new(&ChunkType::from_str("ruSt").unwrap(), "Title", "Dice").is_err());
+    }
+
+    #[test]
+    fn test_text_entry_display() {
+        let text = InternationalTextChunk::new("Title", "Würfel").unwrap();
+        assert_eq!(TextEntry::International(text.clone()).to_string(), "Title: Würfel");
+
+        let text = text.with_language("de", "Titel").unwrap().compressed();
+        assert_eq!(TextEntry::International(text).to_string(), "Title (de, Titel, compressed): Würfel");
+    }
+
+    #[test]
+    fn test_international_round_trip() {
+        let text = InternationalTextChunk::new("Title", "Würfel ☃").unwrap()
+            .with_language("de-CH", "Titel").unwrap();
+        let chunk = text.to_chunk();
+
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        assert_eq!(chunk.data(), "Title\0\0\0de-CH\0Titel\0Würfel ☃".as_bytes());
+        assert_eq!(InternationalTextChunk::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_international_compressed_round_trip() {
+        let text = InternationalTextChunk::new("Description", &"☃ snow ".repeat(50)).unwrap().compressed();
+        let chunk = text.to_chunk();
+        let parsed = InternationalTextChunk::try_from(&chunk).unwrap();
+
+        assert!(chunk.data().starts_with(b"Description\0\x01\0\0\0"));
+        assert!(parsed.is_compressed());
+        assert_eq!(parsed.language_tag(), "");
+        assert_eq!(parsed, text);
+    }
+
+    #[test]
+    fn test_international_invalid_fields() {
+        let chunk = |data: &[u8]| Chunk::new(ChunkType::from_str("iTXt").unwrap(), data.to_vec());
+
+        assert!(InternationalTextChunk::try_from(&chunk(b"Title\0\x02\0\0\0text")).is_err());
+        assert!(InternationalTextChunk::try_from(&chunk(b"Title\0\0\x01\0\0text")).is_err());
+        assert!(InternationalTextChunk::try_from(&chunk(b"Title\0\0\0en\0")).is_err());
+        assert!(InternationalTextChunk::try_from(&chunk(b"Title\0\0\0en\0\0\xff")).is_err());
+        assert!(InternationalTextChunk::try_from(&chunk(b"Title\0\0\0en\0\0text")).is_ok());
+    }
+
+    #[test]
+    fn test_language_tag() {
+        let text = InternationalTextChunk::new("Title", "text").unwrap();
+
+        assert!(text.clone().with_language("en", "").is_ok());
+        assert!(text.clone().with_language("x-klingon", "").is_ok());
+        assert!(text.clone().with_language("en_US", "").is_err());
+        assert!(text.clone().with_language("en--US", "").is_err());
+        assert!(text.with_language("toolongword", "").is_err());
+    }
+}
@@ -0,0 +1,426 @@
+use std::fmt;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+
+/// A way in which a chunk sequence breaks the ordering and
+/// multiplicity rules of the PNG specification.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A required chunk does not appear at all.
+    Missing(ChunkType),
+
+    /// The chunk at `index` must be the first chunk in the file.
+    MustBeFirst { chunk_type: ChunkType, index: usize },
+
+    /// The chunk at `index` must be the last chunk in the file.
+    MustBeLast { chunk_type: ChunkType, index: usize },
+
+    /// The chunk at `index` appears more than once.
+    Duplicate { chunk_type: ChunkType, index: usize },
+
+    /// The chunk at `index` must come before any `other` chunk.
+    MustPrecede { chunk_type: ChunkType, index: usize, other: ChunkType },
+
+    /// The chunk at `index` must come after an `other` chunk.
+    MustFollow { chunk_type: ChunkType, index: usize, other: ChunkType },
+
+    /// The chunk at `index` must be adjacent to the other chunks of its type.
+    NotConsecutive { chunk_type: ChunkType, index: usize },
+
+    /// The chunk at `index` must not appear together with an `other` chunk.
+    MutuallyExclusive { chunk_type: ChunkType, index: usize, other: ChunkType },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Missing(chunk_type) => write!(f, "missing {} chunk", chunk_type),
+            Violation::MustBeFirst { chunk_type, index } => {
+                write!(f, "{} at chunk {} must be the first chunk", chunk_type, index)
+            }
+            Violation::MustBeLast { chunk_type, index } => {
+                write!(f, "{} at chunk {} must be the last chunk", chunk_type, index)
+            }
+            Violation::Duplicate { chunk_type, index } => {
+                write!(f, "{} at chunk {} appears more than once", chunk_type, index)
+            }
+            Violation::MustPrecede { chunk_type, index, other } => {
+                write!(f, "{} at chunk {} must come before {}", chunk_type, index, other)
+            }
+            Violation::MustFollow { chunk_type, index, other } => {
+                write!(f, "{} at chunk {} must come after {}", chunk_type, index, other)
+            }
+            Violation::NotConsecutive { chunk_type, index } => {
+                write!(f, "{} at chunk {} is not consecutive with the previous {}", chunk_type, index, chunk_type)
+            }
+            Violation::MutuallyExclusive { chunk_type, index, other } => {
+                write!(f, "{} at chunk {} must not appear together with {}", chunk_type, index, other)
+            }
+        }
+    }
+}
+
+
+/// Where a chunk type may appear relative to `PLTE` and `IDAT`,
+/// and how many times.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Placement {
+    pub before_plte: bool,
+    pub after_plte: bool,
+    pub before_idat: bool,
+    pub unique: bool,
+}
+
+impl Placement {
+    const ANYWHERE: Placement = Placement { before_plte: false, after_plte: false, before_idat: false, unique: false };
+
+    /// The ordering rules the specification gives for `chunk_type`.
+    /// Unknown chunk types may appear anywhere, any number of times.
+    pub fn of(chunk_type: &ChunkType) -> Placement {
+        let any = Placement::ANYWHERE;
+
+        match chunk_type.bytes() {
+            b"IHDR" | b"IEND" => Placement { unique: true, ..any },
+            b"PLTE" => Placement { before_idat: true, unique: true, ..any },
+            b"cHRM" | b"gAMA" | b"iCCP" | b"sBIT" | b"sRGB" => {
+                Placement { before_plte: true, before_idat: true, unique: true, ..any }
+            }
+            b"bKGD" | b"hIST" | b"tRNS" => {
+                Placement { after_plte: true, before_idat: true, unique: true, ..any }
+            }
+            b"pHYs" => Placement { before_idat: true, unique: true, ..any },
+            b"sPLT" => Placement { before_idat: true, ..any },
+            b"tIME" => Placement { unique: true, ..any },
+            _ => any,
+        }
+    }
+}
+
+
+/// Where to insert a new chunk.
+///
+/// The position is only a request: it is moved as little as needed
+/// to satisfy the ordering rules of known chunk types.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Position {
+    /// Right before `IEND`, or at the end if there is none.
+    #[default]
+    BeforeIend,
+    /// Right before the first `IDAT`.
+    BeforeIdat,
+    /// Right after the last chunk of the given type.
+    After(ChunkType),
+}
+
+/// The critical chunk types of the specification, which hold the image
+/// and so are never inserted next to it.
+const STANDARD_CRITICAL: [&[u8; 4]; 4] = [b"IHDR", b"PLTE", b"IDAT", b"IEND"];
+
+/// Finds the index at which a chunk of `chunk_type` should be inserted
+/// into `chunk_types` to honor `position` and the ordering rules.
+pub(crate) fn insertion_index(
+    chunk_types: &[&ChunkType],
+    chunk_type: &ChunkType,
+    position: &Position,
+) -> Result<usize, PngError> {
+    if STANDARD_CRITICAL.contains(&chunk_type.bytes()) {
+        return Err(PngError::CriticalChunk(chunk_type.to_string()));
+    }
+
+    let placement = Placement::of(chunk_type);
+    let first = |name: &[u8; 4]| chunk_types.iter().position(|t| t.bytes() == name);
+    let last = |name: &[u8; 4]| chunk_types.iter().rposition(|t| t.bytes() == name);
+
+    if placement.unique && chunk_types.contains(&chunk_type) {
+        return Err(PngError::DuplicateChunk(chunk_type.to_string()));
+    }
+
+    let end = last(b"IEND").unwrap_or(chunk_types.len());
+    let requested = match position {
+        Position::BeforeIend => end,
+        Position::BeforeIdat => first(b"IDAT").unwrap_or(end),
+        Position::After(other) => match chunk_types.iter().rposition(|t| *t == other) {
+            Some(i) => i + 1,
+            None => return Err(PngError::ChunkNotFound(other.to_string())),
+        },
+    };
+
+    let mut lowest = first(b"IHDR").map_or(0, |i| i + 1);
+    let mut highest = end;
+
+    if let Some(plte) = first(b"PLTE") {
+        if placement.after_plte {
+            lowest = lowest.max(plte + 1);
+        }
+        if placement.before_plte {
+            highest = highest.min(plte);
+        }
+    }
+
+    if let Some(idat) = first(b"IDAT") {
+        if placement.before_idat {
+            highest = highest.min(idat);
+        }
+    }
+
+    if lowest > highest {
+        return Err(PngError::NoValidPosition(chunk_type.to_string()));
+    }
+
+    Ok(requested.max(lowest).min(highest))
+}
+
+
+/// Checks `chunk_types` against the ordering and multiplicity rules
+/// of the PNG specification, returning every violation found.
+pub fn check_chunk_order(chunk_types: &[&ChunkType]) -> Vec<Violation> {
+    let mut violations = vec![];
+    let is = |chunk_type: &ChunkType, name: &[u8; 4]| chunk_type.bytes() == name;
+    let position = |name: &[u8; 4]| chunk_types.iter().position(|t| is(t, name));
+
+    let ihdr = known_type(b"IHDR");
+    let plte = known_type(b"PLTE");
+    let idat = known_type(b"IDAT");
+    let iend = known_type(b"IEND");
+
+    let first_plte = position(b"PLTE");
+    let first_idat = position(b"IDAT");
+    let srgb = position(b"sRGB");
+    let iccp = position(b"iCCP");
+
+    match position(b"IHDR") {
+        Some(0) => {}
+        Some(index) => violations.push(Violation::MustBeFirst { chunk_type: ihdr, index }),
+        None => violations.push(Violation::Missing(ihdr)),
+    }
+
+    if first_idat.is_none() {
+        violations.push(Violation::Missing(idat.clone()));
+    }
+
+    if position(b"IEND").is_none() {
+        violations.push(Violation::Missing(iend));
+    }
+
+    for (index, &chunk_type) in chunk_types.iter().enumerate() {
+        let placement = Placement::of(chunk_type);
+        let earlier = &chunk_types[..index];
+
+        if placement.unique && earlier.contains(&chunk_type) {
+            violations.push(Violation::Duplicate { chunk_type: chunk_type.clone(), index });
+        }
+
+        if is(chunk_type, b"IEND") && index + 1 != chunk_types.len() {
+            violations.push(Violation::MustBeLast { chunk_type: chunk_type.clone(), index });
+        }
+
+        if is(chunk_type, b"IDAT") && index > 0 && earlier.contains(&chunk_type) && chunk_types[index - 1] != chunk_type {
+            violations.push(Violation::NotConsecutive { chunk_type: chunk_type.clone(), index });
+        }
+
+        if placement.before_plte && first_plte.is_some_and(|i| i < index) {
+            violations.push(Violation::MustPrecede { chunk_type: chunk_type.clone(), index, other: plte.clone() });
+        }
+
+        if placement.before_idat && first_idat.is_some_and(|i| i < index) {
+            violations.push(Violation::MustPrecede { chunk_type: chunk_type.clone(), index, other: idat.clone() });
+        }
+
+        // hIST is only meaningful with a palette; bKGD and tRNS only
+        // need to follow PLTE when there is one.
+        let needs_plte = is(chunk_type, b"hIST") || first_plte.is_some();
+        if placement.after_plte && needs_plte && first_plte.is_none_or(|i| i > index) {
+            violations.push(Violation::MustFollow { chunk_type: chunk_type.clone(), index, other: plte.clone() });
+        }
+    }
+
+    if let (Some(srgb), Some(iccp)) = (srgb, iccp) {
+        violations.push(Violation::MutuallyExclusive {
+            chunk_type: chunk_types[srgb.max(iccp)].clone(),
+            index: srgb.max(iccp),
+            other: chunk_types[srgb.min(iccp)].clone(),
+        });
+    }
+
+    violations
+}
+
+fn known_type(name: &[u8; 4]) -> ChunkType {
+    ChunkType { bytes: *name }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn check(types: &[&str]) -> Vec<Violation> {
+        let types: Vec<ChunkType> = types.iter().map(|t| ChunkType::from_str(t).unwrap()).collect();
+        let refs: Vec<&ChunkType> = types.iter().collect();
+        check_chunk_order(&refs)
+    }
+
+    fn chunk_type(s: &str) -> ChunkType {
+        ChunkType::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_valid_order() {
+        let violations = check(&["IHDR", "gAMA", "PLTE", "tRNS", "pHYs", "IDAT", "IDAT", "tEXt", "IEND"]);
+        assert!(violations.is_empty());
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        let violations = check(&["tEXt"]);
+        assert_eq!(violations, [
+            Violation::Missing(chunk_type("IHDR")),
+            Violation::Missing(chunk_type("IDAT")),
+            Violation::Missing(chunk_type("IEND")),
+        ]);
+    }
+
+    #[test]
+    fn test_duplicate_ihdr() {
+        let violations = check(&["IHDR", "IHDR", "IDAT", "IEND"]);
+        assert_eq!(violations, [Violation::Duplicate { chunk_type: chunk_type("IHDR"), index: 1 }]);
+    }
+
+    #[test]
+    fn test_ihdr_not_first() {
+        let violations = check(&["tEXt", "IHDR", "IDAT", "IEND"]);
+        assert_eq!(violations, [Violation::MustBeFirst { chunk_type: chunk_type("IHDR"), index: 1 }]);
+    }
+
+    #[test]
+    fn test_chunk_after_iend() {
+        let violations = check(&["IHDR", "IDAT", "IEND", "ruSt"]);
+        assert_eq!(violations, [Violation::MustBeLast { chunk_type: chunk_type("IEND"), index: 2 }]);
+    }
+
+    #[test]
+    fn test_trns_after_idat() {
+        let violations = check(&["IHDR", "IDAT", "tRNS", "IEND"]);
+        assert_eq!(violations, [
+            Violation::MustPrecede { chunk_type: chunk_type("tRNS"), index: 2, other: chunk_type("IDAT") },
+        ]);
+    }
+
+    #[test]
+    fn test_gama_after_plte() {
+        let violations = check(&["IHDR", "PLTE", "gAMA", "IDAT", "IEND"]);
+        assert_eq!(violations, [
+            Violation::MustPrecede { chunk_type: chunk_type("gAMA"), index: 2, other: chunk_type("PLTE") },
+        ]);
+    }
+
+    #[test]
+    fn test_bkgd_before_plte() {
+        let violations = check(&["IHDR", "bKGD", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(violations, [
+            Violation::MustFollow { chunk_type: chunk_type("bKGD"), index: 1, other: chunk_type("PLTE") },
+        ]);
+    }
+
+    #[test]
+    fn test_hist_without_plte() {
+        let violations = check(&["IHDR", "hIST", "IDAT", "IEND"]);
+        assert_eq!(violations, [
+            Violation::MustFollow { chunk_type: chunk_type("hIST"), index: 1, other: chunk_type("PLTE") },
+        ]);
+    }
+
+    #[test]
+    fn test_non_consecutive_idat() {
+        let violations = check(&["IHDR", "IDAT", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(violations, [Violation::NotConsecutive { chunk_type: chunk_type("IDAT"), index: 3 }]);
+    }
+
+    #[test]
+    fn test_plte_after_idat() {
+        let violations = check(&["IHDR", "IDAT", "PLTE", "IEND"]);
+        assert_eq!(violations, [
+            Violation::MustPrecede { chunk_type: chunk_type("PLTE"), index: 2, other: chunk_type("IDAT") },
+        ]);
+    }
+
+    #[test]
+    fn test_srgb_and_iccp() {
+        let violations = check(&["IHDR", "sRGB", "iCCP", "IDAT", "IEND"]);
+        assert_eq!(violations, [
+            Violation::MutuallyExclusive { chunk_type: chunk_type("iCCP"), index: 2, other: chunk_type("sRGB") },
+        ]);
+    }
+
+    fn insert(types: &[&str], new_type: &str, position: Position) -> Result<usize, PngError> {
+        let types: Vec<ChunkType> = types.iter().map(|t| chunk_type(t)).collect();
+        let refs: Vec<&ChunkType> = types.iter().collect();
+        insertion_index(&refs, &chunk_type(new_type), &position)
+    }
+
+    #[test]
+    fn test_insert_before_iend() {
+        let types = ["IHDR", "IDAT", "IEND"];
+        assert_eq!(insert(&types, "ruSt", Position::BeforeIend).unwrap(), 2);
+        assert_eq!(insert(&types[..2], "ruSt", Position::BeforeIend).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_insert_before_idat() {
+        let types = ["IHDR", "gAMA", "IDAT", "IDAT", "IEND"];
+        assert_eq!(insert(&types, "ruSt", Position::BeforeIdat).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_insert_after_chunk() {
+        let types = ["IHDR", "gAMA", "IDAT", "tEXt", "IEND"];
+        assert_eq!(insert(&types, "ruSt", Position::After(chunk_type("gAMA"))).unwrap(), 2);
+        assert_eq!(insert(&types, "ruSt", Position::After(chunk_type("tEXt"))).unwrap(), 4);
+        assert!(matches!(
+            insert(&types, "ruSt", Position::After(chunk_type("zTXt"))),
+            Err(PngError::ChunkNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_insert_never_after_iend_or_before_ihdr() {
+        let types = ["IHDR", "IDAT", "IEND", "tEXt"];
+        assert_eq!(insert(&types, "ruSt", Position::After(chunk_type("tEXt"))).unwrap(), 2);
+        assert_eq!(insert(&types, "ruSt", Position::After(chunk_type("IEND"))).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_insert_honors_known_chunk_rules() {
+        let types = ["IHDR", "PLTE", "IDAT", "IEND"];
+        assert_eq!(insert(&types, "tRNS", Position::BeforeIend).unwrap(), 2);
+        assert_eq!(insert(&types, "gAMA", Position::BeforeIend).unwrap(), 1);
+        assert_eq!(insert(&types, "bKGD", Position::After(chunk_type("IHDR"))).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_insert_duplicate_unique_chunk() {
+        let types = ["IHDR", "gAMA", "IDAT", "IEND"];
+        assert!(matches!(insert(&types, "gAMA", Position::BeforeIdat), Err(PngError::DuplicateChunk(_))));
+    }
+
+    #[test]
+    fn test_insert_without_valid_position() {
+        let types = ["IHDR", "IDAT", "PLTE", "IEND"];
+        assert!(matches!(insert(&types, "tRNS", Position::BeforeIend), Err(PngError::NoValidPosition(_))));
+    }
+
+    #[test]
+    fn test_insert_critical_chunk() {
+        let types = ["IHDR", "gAMA", "IDAT", "IEND"];
+        for critical in ["IHDR", "PLTE", "IDAT", "IEND"] {
+            assert!(matches!(insert(&types, critical, Position::BeforeIend), Err(PngError::CriticalChunk(_))));
+        }
+    }
+
+    #[test]
+    fn test_violation_display() {
+        let violation = Violation::MustPrecede { chunk_type: chunk_type("tRNS"), index: 5, other: chunk_type("IDAT") };
+        assert_eq!(violation.to_string(), "tRNS at chunk 5 must come before IDAT");
+    }
+}
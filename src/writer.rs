@@ -0,0 +1,72 @@
+use std::io::Write;
+use crate::chunk::Chunk;
+use crate::error::PngError;
+use crate::png::Png;
+
+/// Writes a PNG stream one chunk at a time, serializing each chunk
+/// straight into the underlying writer.
+pub struct PngWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PngWriter<W> {
+    /// Creates a writer over `writer` and writes the PNG signature.
+    pub fn new(mut writer: W) -> Result<Self, PngError> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        Ok(PngWriter { writer })
+    }
+
+    /// Writes the length, type, data and CRC of `chunk`.
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<(), PngError> {
+        chunk.write_to(&mut self.writer)?;
+        Ok(())
+    }
+
+    /// Gives access to the underlying writer, for copying raw chunk bytes.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Flushes the underlying writer and returns it.
+    pub fn finish(mut self) -> Result<W, PngError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::reader::PngReader;
+    use std::str::FromStr;
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ]
+    }
+
+    #[test]
+    fn test_write_signature_only() {
+        let bytes = PngWriter::new(Vec::new()).unwrap().finish().unwrap();
+        assert_eq!(bytes, Png::STANDARD_HEADER);
+    }
+
+    #[test]
+    fn test_write_chunks() {
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        for chunk in testing_chunks().iter() {
+            writer.write_chunk(chunk).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        assert_eq!(bytes, Png::from_chunks(testing_chunks()).as_bytes());
+
+        let chunks: Vec<Chunk> = PngReader::new(&bytes[..]).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(chunks[1].data_as_string().unwrap(), "hello");
+    }
+}
@@ -0,0 +1,54 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::inflate::{decompress_to_vec_zlib_with_limit, TINFLStatus};
+use crate::error::PngError;
+
+/// Compression level used for everything this crate deflates.
+const COMPRESSION_LEVEL: u8 = 9;
+
+/// Compresses `data` into a zlib stream, the only format PNG allows.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    compress_to_vec_zlib(data, COMPRESSION_LEVEL)
+}
+
+/// Decompresses a zlib stream, checking its header and Adler-32 checksum.
+/// Streams that inflate to more than `max_len` bytes are rejected as soon
+/// as they pass it, so a few bytes of input cannot exhaust memory.
+pub fn decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>, PngError> {
+    decompress_to_vec_zlib_with_limit(data, max_len).map_err(|e| match e.status {
+        TINFLStatus::HasMoreOutput => PngError::Zlib(format!("stream inflates to more than {} bytes", max_len)),
+        _ => PngError::Zlib(e.to_string()),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"hello hello hello hello hello".to_vec();
+        let compressed = compress(&data);
+
+        assert_eq!(compressed[0] & 0x0f, 8);
+        assert_eq!(decompress(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_limit() {
+        let compressed = compress(&[0; 1000]);
+
+        assert!(decompress(&compressed, 1000).is_ok());
+        assert!(matches!(decompress(&compressed, 999), Err(PngError::Zlib(e)) if e.contains("more than 999 bytes")));
+    }
+
+    #[test]
+    fn test_corrupt_stream() {
+        let mut compressed = compress(b"hello");
+        let last = compressed.len() - 1;
+        compressed[last] ^= 0xff;
+
+        assert!(matches!(decompress(&compressed, 100), Err(PngError::Zlib(_))));
+        assert!(matches!(decompress(b"not zlib", 100), Err(PngError::Zlib(_))));
+    }
+}